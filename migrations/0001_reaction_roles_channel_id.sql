@@ -0,0 +1,3 @@
+/* Remember which channel a reaction role's message is in, so it can be linked to and cleaned up.
+   Rows created before this can't be backfilled, and are left with a NULL channel. */
+ALTER TABLE reaction_roles ADD COLUMN IF NOT EXISTS channel_id bigint;
@@ -0,0 +1,13 @@
+/* Key reaction roles on (guild, message, emoji, role) rather than on the role alone,
+   so one role can be offered on several messages, and one emoji can give several roles. */
+BEGIN;
+
+ALTER TABLE reaction_roles ADD COLUMN IF NOT EXISTS emoji text;
+UPDATE reaction_roles SET emoji = COALESCE(emoji_id::text, name) WHERE emoji IS NULL;
+ALTER TABLE reaction_roles ALTER COLUMN emoji SET NOT NULL;
+
+ALTER TABLE reaction_roles DROP CONSTRAINT IF EXISTS reaction_roles_pkey;
+ALTER TABLE reaction_roles DROP CONSTRAINT IF EXISTS reaction_roles_role_id_key;
+ALTER TABLE reaction_roles ADD PRIMARY KEY (guild_id, message_id, emoji, role_id);
+
+COMMIT;
@@ -0,0 +1,2 @@
+/* Allow reaction roles to be grouped into "pick one" sets */
+ALTER TABLE reaction_roles ADD COLUMN IF NOT EXISTS exclusive_group text;
@@ -0,0 +1,3 @@
+/* Let each reaction role choose what adding and removing its reaction does */
+ALTER TABLE reaction_roles ADD COLUMN IF NOT EXISTS mode text NOT NULL DEFAULT 'toggle'
+    CHECK (mode IN ('toggle', 'add_only', 'remove_only', 'verify'));
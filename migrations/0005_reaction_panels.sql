@@ -0,0 +1,10 @@
+/* Bot managed reaction role panels */
+ALTER TABLE reaction_roles ADD COLUMN IF NOT EXISTS description text;
+ALTER TABLE reaction_roles ADD COLUMN IF NOT EXISTS added_at timestamptz NOT NULL DEFAULT now();
+
+CREATE TABLE IF NOT EXISTS reaction_panels (
+    message_id bigint PRIMARY KEY NOT NULL,
+    guild_id bigint NOT NULL,
+    channel_id bigint NOT NULL,
+    title text NOT NULL
+);
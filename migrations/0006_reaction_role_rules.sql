@@ -0,0 +1,10 @@
+/* Prerequisite roles and per message role limits for reaction roles */
+ALTER TABLE reaction_roles ADD COLUMN IF NOT EXISTS required_role_id bigint;
+
+CREATE TABLE IF NOT EXISTS reaction_role_rules (
+    message_id bigint PRIMARY KEY NOT NULL,
+    guild_id bigint NOT NULL,
+    channel_id bigint NOT NULL,
+    max_roles integer CHECK (max_roles > 0),
+    dm_on_reject boolean NOT NULL DEFAULT false
+);
@@ -0,0 +1,2 @@
+/* Channel for reporting reaction roles removed after their role, emoji or message is deleted */
+ALTER TABLE guild ADD COLUMN IF NOT EXISTS log_channel_id bigint;
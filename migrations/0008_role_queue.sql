@@ -0,0 +1,14 @@
+/* Persistent queue for the role changes reaction roles make, so failed ones can be retried */
+CREATE TABLE IF NOT EXISTS role_queue (
+    guild_id bigint NOT NULL,
+    user_id bigint NOT NULL,
+    role_id bigint NOT NULL,
+    action text NOT NULL CHECK (action IN ('add', 'remove')),
+    status text NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'failed')),
+    attempts integer NOT NULL DEFAULT 0,
+    last_error text,
+    next_attempt_at timestamptz NOT NULL DEFAULT now(),
+    queued_at timestamptz NOT NULL DEFAULT now(),
+    revision integer NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild_id, user_id, role_id)
+);
@@ -0,0 +1,4 @@
+/* The reaction behind each queued role change, for the audit log posted to the log channel */
+ALTER TABLE role_queue ADD COLUMN IF NOT EXISTS channel_id bigint;
+ALTER TABLE role_queue ADD COLUMN IF NOT EXISTS message_id bigint;
+ALTER TABLE role_queue ADD COLUMN IF NOT EXISTS emoji text;
@@ -0,0 +1,14 @@
+/* Guilds can have several prefixes, instead of a single one on the guild row */
+CREATE TABLE IF NOT EXISTS guild_prefixes (
+    guild_id bigint NOT NULL,
+    prefix text NOT NULL,
+    added_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (guild_id, prefix)
+);
+
+/* Commands are case insensitive, so prefixes are stored in lowercase to match */
+INSERT INTO guild_prefixes (guild_id, prefix)
+SELECT id, lower(prefix) FROM guild WHERE lower(prefix) <> 'a.'
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE guild DROP COLUMN IF EXISTS prefix;
@@ -3,13 +3,74 @@ CREATE TABLE IF NOT EXISTS guild (
     /* TODO make varchar(100) instead of text */
     name text NOT NULL,
     updated_at timestamptz,
-    prefix text NOT NULL DEFAULT 'a.'
+    /* Where changes to the guild's reaction roles, and the roles members pick up or drop through them, are reported */
+    log_channel_id bigint
+);
+
+/* Guilds without any prefixes here use the default prefix */
+CREATE TABLE IF NOT EXISTS guild_prefixes (
+    guild_id bigint NOT NULL,
+    prefix text NOT NULL,
+    added_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (guild_id, prefix)
 );
 
 CREATE TABLE IF NOT EXISTS reaction_roles (
     guild_id bigint NOT NULL,
-    role_id bigint UNIQUE PRIMARY KEY NOT NULL,
+    role_id bigint NOT NULL,
+    channel_id bigint,
     message_id bigint NOT NULL,
+    /* The emoji's id for custom emojis, or the emoji itself for unicode emojis */
+    emoji text NOT NULL,
     name text NOT NULL,
-    emoji_id bigint
+    emoji_id bigint,
+    /* Reaction roles sharing a group only let a member have one of their roles at a time */
+    exclusive_group text,
+    mode text NOT NULL DEFAULT 'toggle' CHECK (mode IN ('toggle', 'add_only', 'remove_only', 'verify')),
+    /* Shown next to the role on panels */
+    description text,
+    added_at timestamptz NOT NULL DEFAULT now(),
+    /* A role members need to already have before this reaction role gives them anything */
+    required_role_id bigint,
+    PRIMARY KEY (guild_id, message_id, emoji, role_id)
+);
+
+/* Messages posted by the bot that list the reaction roles on them */
+CREATE TABLE IF NOT EXISTS reaction_panels (
+    message_id bigint PRIMARY KEY NOT NULL,
+    guild_id bigint NOT NULL,
+    channel_id bigint NOT NULL,
+    title text NOT NULL
+);
+
+/* Rules for every reaction role on a message */
+CREATE TABLE IF NOT EXISTS reaction_role_rules (
+    message_id bigint PRIMARY KEY NOT NULL,
+    guild_id bigint NOT NULL,
+    channel_id bigint NOT NULL,
+    /* How many of the message's roles a member can have at once */
+    max_roles integer CHECK (max_roles > 0),
+    /* DM members when their reaction is removed for breaking a rule */
+    dm_on_reject boolean NOT NULL DEFAULT false
+);
+
+/* Role changes from reaction roles waiting to be made, only the latest change for each member's role is kept */
+CREATE TABLE IF NOT EXISTS role_queue (
+    guild_id bigint NOT NULL,
+    user_id bigint NOT NULL,
+    role_id bigint NOT NULL,
+    action text NOT NULL CHECK (action IN ('add', 'remove')),
+    /* Failed changes are kept around for admins to see, and aren't retried */
+    status text NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'failed')),
+    attempts integer NOT NULL DEFAULT 0,
+    last_error text,
+    next_attempt_at timestamptz NOT NULL DEFAULT now(),
+    queued_at timestamptz NOT NULL DEFAULT now(),
+    /* Bumped whenever the change is replaced, so a change that finishes late doesn't clear its replacement */
+    revision integer NOT NULL DEFAULT 0,
+    /* The reaction that caused the change, for the audit log */
+    channel_id bigint,
+    message_id bigint,
+    emoji text,
+    PRIMARY KEY (guild_id, user_id, role_id)
 );
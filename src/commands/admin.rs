@@ -1,9 +1,29 @@
-use crate::core::structs::{
-    PoolContainer,
-    PrefixHashMapContainer,
-    TokioContainer,
+use crate::core::{
+    panels::refresh_panel,
+    prefixes::{
+        default_prefix,
+        describe_prefixes,
+        guild_prefixes,
+        normalize_prefix,
+        reload_prefixes,
+        risky_prefix,
+        validate_prefix,
+        MAX_PREFIXES,
+    },
+    reaction_cache::refresh_reaction_messages,
+    structs::{
+        PoolContainer,
+        ReactionMode,
+    },
+    sync::sync_reaction_roles,
+    utils::{
+        emoji_display,
+        message_url,
+        reaction_type_from_row,
+    },
 };
 use lazy_static::lazy_static;
+use log::warn;
 use regex::Regex;
 use serenity::{
     framework::standard::{
@@ -12,119 +32,636 @@ use serenity::{
         CommandError,
         CommandResult,
     },
-    model::prelude::{
-        Message,
-        MessageId,
-        RoleId,
+    http::error::Error as HttpError,
+    model::{
+        id::EmojiId,
+        misc::EmojiIdentifier,
+        prelude::{
+            ChannelId,
+            GuildId,
+            Message,
+            MessageId,
+            ReactionType,
+            RoleId,
+            UserId,
+        },
+    },
+    prelude::{
+        Context,
+        Mentionable,
     },
-    prelude::Context,
+    utils::parse_channel,
 };
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::Duration,
+};
+
+/// How long to wait for someone to confirm a command, before cancelling it
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[command]
 #[aliases("pre")]
 #[required_permissions(MANAGE_ROLES)]
-/// Sets the bot's prefix in a server to whatever argument is provided
-/// If no new prefix is provided, it will show the server's current prefix
+#[sub_commands(prefix_add, prefix_remove, prefix_list, prefix_reset)]
+/// Shows the server's prefixes, or replaces all of them with the one given.
+/// Mentioning the bot always works as a prefix too, in case the others are forgotten.
+///
+/// Prefixes can be up to 20 characters, and can't start or end with a space,
+/// or have mentions in them. Like commands, they're case insensitive. Prefixes that might make the bot hard to use,
+/// like ones that are only letters, have to be confirmed first.
+///
+/// Example usage:
+/// prefix [new prefix]
+/// prefix add <prefix>
+/// prefix remove <prefix>
+/// prefix list
+/// prefix reset
+///
 /// Restricted to Users with the Administrator permission
-fn prefix(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+async fn prefix(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    if args.is_empty() {
+        return show_prefixes(ctx, msg).await;
+    }
+
     let guild_id = msg.guild_id.ok_or("Failed to get server ID")?;
-    let (fancy_db, tokio_lock, prefix_hashmap_lock) = {
-        let data = ctx.data.try_read().ok_or("Failed to get data lock")?;
-        let fancy_db = Arc::clone(
+    let prefix = normalize_prefix(args.rest());
+    if !check_new_prefix(ctx, msg, &prefix).await? {
+        return Ok(());
+    }
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
             data.get::<PoolContainer>()
                 .ok_or("Failed to get database pool out of data")?,
-        );
-        let tokio_lock = Arc::clone(
-            data.get::<TokioContainer>()
-                .ok_or("Failed to get runtime")?,
-        );
-        let prefix_hashmap_lock = Arc::clone(
-            data.get::<PrefixHashMapContainer>()
-                .ok_or("Failed to get prefix cache lock")?,
-        );
-        (fancy_db, tokio_lock, prefix_hashmap_lock)
+        )
     };
 
-    // Limit the scope of the prefix hashmap lock
-    {
-        let mut prefix_hashmap = prefix_hashmap_lock
-            .try_lock()
-            .ok()
-            .ok_or("Failed to get prefix cache")?;
-
-        if args.is_empty() {
-            if let Some(prefix) = prefix_hashmap.get(&guild_id.0) {
-                return msg
-                    .channel_id
-                    .say(&ctx.http, format!("This guild's prefix is ``{}``", prefix))
-                    .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(()));
-            }
-            let prefix_option = {
-                let mut tokio = tokio_lock
-                    .try_lock()
-                    .ok()
-                    .ok_or("Failed to get runtime lock")?;
-                tokio
-                    .block_on(
-                        sqlx::query!("SELECT prefix FROM guild WHERE id = $1", guild_id.0 as i64)
-                            .fetch_optional(fancy_db.pool()),
-                    )
-                    .ok()
-                    .ok_or("Failed to get prefix from database")?
-            };
+    let mut transaction = fancy_db.pool().begin().await?;
+    sqlx::query!(
+        "DELETE FROM guild_prefixes WHERE guild_id = $1",
+        guild_id.0 as i64
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO guild_prefixes (guild_id, prefix) VALUES ($1, $2)",
+        guild_id.0 as i64,
+        prefix
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    reload_prefixes(ctx, guild_id).await?;
 
-            if let Some(result) = prefix_option {
-                prefix_hashmap.insert(guild_id.0, result.prefix.clone());
-                return msg
-                    .channel_id
-                    .say(
-                        &ctx.http,
-                        format!("This servers prefix is ``{}``", result.prefix),
-                    )
-                    .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(()));
-            } else {
-                return msg
-                    .channel_id
-                    .say(&ctx.http, "This servers  prefix is ``a.``")
-                    .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(()));
-            }
-        }
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Changed the server's prefix to ``{}``\n{}",
+                prefix,
+                reset_reminder(ctx).await
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
 
-        prefix_hashmap.insert(guild_id.0, args.rest().to_string());
+#[command("add")]
+#[min_args(1)]
+#[required_permissions(MANAGE_ROLES)]
+/// Adds another prefix the bot will answer to in this server, keeping the existing ones.
+///
+/// Example usage:
+/// prefix add <prefix>
+/// prefix add !
+async fn prefix_add(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Failed to get server ID")?;
+    let prefix = normalize_prefix(args.rest());
+    validate_prefix(&prefix)?;
+    let prefixes = guild_prefixes(ctx, guild_id).await?;
+    if prefixes.contains(&prefix) {
+        return Err(format!("``{}`` is already one of this server's prefixes", prefix).into());
+    }
+    if prefixes.len() >= MAX_PREFIXES {
+        return Err(format!(
+            "Servers can only have {} prefixes, remove one first",
+            MAX_PREFIXES
+        )
+        .into());
+    }
+    if !check_new_prefix(ctx, msg, &prefix).await? {
+        return Ok(());
     }
 
-    let guild_name = msg
-        .guild(&ctx)
-        .ok_or("Failed to get guild")?
-        .try_read()
-        .ok_or("Failed to get guild lock")?
-        .name
-        .clone();
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or("Failed to get database pool out of data")?,
+        )
+    };
 
-    // At this point we don't need args anymore, and can just reassign it as a String.
-    let args = args.rest().to_owned();
+    // A server without prefixes of its own is using the default one,
+    // which would stop working once it has any other prefix, unless it's kept.
+    let mut new_prefixes = Vec::new();
+    if prefixes.is_empty() {
+        new_prefixes.push(default_prefix(ctx).await?);
+    }
+    new_prefixes.push(prefix.clone());
+    for new_prefix in new_prefixes {
+        sqlx::query!(
+            "INSERT INTO guild_prefixes (guild_id, prefix) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            guild_id.0 as i64,
+            new_prefix
+        )
+        .execute(fancy_db.pool())
+        .await?;
+    }
+    reload_prefixes(ctx, guild_id).await?;
 
-    // Limit scope of tokio lock
-    {
-        let mut tokio = tokio_lock.try_lock()?;
-        tokio.block_on(
-            sqlx::query!(
-                "INSERT INTO guild (id, name, prefix) VALUES ($1, $2, $3) ON CONFLICT (id) DO UPDATE SET name = $2, prefix = $3",
-                guild_id.0 as i64,
-                guild_name,
-                args
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Added ``{}`` as a prefix\n{}",
+                prefix,
+                reset_reminder(ctx).await
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command("remove")]
+#[min_args(1)]
+#[required_permissions(MANAGE_ROLES)]
+/// Stops the bot answering to one of this server's prefixes.
+/// Removing the last one puts the server back on the default prefix.
+///
+/// Example usage:
+/// prefix remove <prefix>
+/// prefix remove !
+async fn prefix_remove(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Failed to get server ID")?;
+    let prefix = normalize_prefix(args.rest());
+    let prefixes = guild_prefixes(ctx, guild_id).await?;
+    if !prefixes.contains(&prefix) {
+        return Err(format!("``{}`` isn't one of this server's prefixes", prefix).into());
+    }
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or("Failed to get database pool out of data")?,
+        )
+    };
+
+    sqlx::query!(
+        "DELETE FROM guild_prefixes WHERE guild_id = $1 AND prefix = $2",
+        guild_id.0 as i64,
+        prefix
+    )
+    .execute(fancy_db.pool())
+    .await?;
+    let prefixes = reload_prefixes(ctx, guild_id).await?;
+
+    let reply = if prefixes.is_empty() {
+        format!(
+            "Removed ``{}``, this server is back on the default prefix ``{}``",
+            prefix,
+            default_prefix(ctx).await?
+        )
+    } else {
+        format!("Removed ``{}`` from this server's prefixes", prefix)
+    };
+    msg.channel_id.say(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command("list")]
+/// Shows every prefix the bot answers to in this server.
+///
+/// Example usage:
+/// prefix list
+async fn prefix_list(ctx: &Context, msg: &Message) -> CommandResult {
+    show_prefixes(ctx, msg).await
+}
+
+#[command("reset")]
+#[required_permissions(MANAGE_ROLES)]
+/// Removes all of this server's prefixes, putting it back on the default prefix.
+/// Mentioning the bot always works, so this can be used even when nobody knows the prefix.
+///
+/// Example usage:
+/// @Arzte prefix reset
+/// prefix reset
+async fn prefix_reset(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Failed to get server ID")?;
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or("Failed to get database pool out of data")?,
+        )
+    };
+
+    sqlx::query!(
+        "DELETE FROM guild_prefixes WHERE guild_id = $1",
+        guild_id.0 as i64
+    )
+    .execute(fancy_db.pool())
+    .await?;
+    reload_prefixes(ctx, guild_id).await?;
+
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Reset this server's prefixes, it's back on the default prefix ``{}``",
+                default_prefix(ctx).await?
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Checks a prefix someone wants to use, asking them to confirm it if it looks risky,
+/// and returning whether it should be used.
+async fn check_new_prefix(
+    ctx: &Context,
+    msg: &Message,
+    prefix: &str,
+) -> Result<bool, CommandError> {
+    validate_prefix(prefix)?;
+
+    match risky_prefix(prefix) {
+        Some(reason) => {
+            confirm(
+                ctx,
+                msg,
+                &format!(
+                    "``{}`` might not be a good prefix: {}.\n{}",
+                    prefix,
+                    reason,
+                    reset_reminder(ctx).await
+                ),
             )
-            .execute(fancy_db.pool()),
-        ).ok().ok_or("Error setting prefix, try again later")?;
+            .await
+        }
+        None => Ok(true),
+    }
+}
+
+/// Reminds people how to get back to the default prefix, if the new one doesn't work out
+async fn reset_reminder(ctx: &Context) -> String {
+    format!(
+        "If it's ever forgotten, <@{}> prefix reset will always put things back.",
+        ctx.cache.current_user_id().await.0
+    )
+}
+
+/// Lists the prefixes the bot answers to in the server a message was sent in
+async fn show_prefixes(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Failed to get server ID")?;
+    msg.channel_id
+        .say(&ctx.http, describe_prefixes(ctx, Some(guild_id)).await?)
+        .await?;
+
+    Ok(())
+}
+
+lazy_static! {
+    // The lazy_static will only panic if the regex itself is invalid, and therefore, shouldn't
+    // ever panic.
+    // regex test link: https://regex101.com/r/Rth5jE/6
+    // rust playground link: https://play.rust-lang.org/?version=stable&mode=debug&edition=2018&gist=7bc300a4af839a35ec3a9c4daf9344da
+    static ref MESSAGE_URL_REGEX: Regex = Regex::new(
+        r"(?m)http[s]?://?(?:ptb\.|canary\.)?discord(?:app)?\.com/channels/\d*/(\d*)/(\d*)",
+    )
+    .expect("Invalid regex, this should never happen.");
+}
+
+/// Parses a message link into the channel and message it points to
+pub(crate) fn parse_message_url(url: &str) -> Result<(ChannelId, MessageId), CommandError> {
+    let capture = MESSAGE_URL_REGEX
+        .captures(url)
+        .ok_or("Couldn't find message id")?;
+    let channel_id = capture
+        .get(1)
+        .ok_or("Couldn't get channel id")?
+        .as_str()
+        .parse::<u64>()?;
+    let message_id = capture
+        .get(2)
+        .ok_or("Couldn't get messsage id")?
+        .as_str()
+        .parse::<u64>()?;
+
+    Ok((ChannelId(channel_id), MessageId(message_id)))
+}
+
+/// Parses an emoji argument, returning the identifier of a custom (or animated) emoji,
+/// or `None` if it looks like a unicode emoji.
+fn parse_emoji_arg(emoji_str: &str) -> Result<Option<EmojiIdentifier>, CommandError> {
+    match serenity::utils::parse_emoji(emoji_str) {
+        Some(emoji) => Ok(Some(emoji)),
+        None => {
+            if emoji_str.starts_with("<a:") {
+                let mut split = emoji_str.split(':');
+                let name = split.nth(1).ok_or("Failed to parse emoji")?;
+                log::debug!("emoji name: {}", name);
+                let id = split
+                    .next()
+                    .ok_or("Failed to get name of emoji")?
+                    .trim_end_matches('>');
+                log::debug!("emoji id: {}", id);
+                Ok(Some(EmojiIdentifier {
+                    id: EmojiId(id.parse::<u64>()?),
+                    name: name.to_string(),
+                }))
+            } else if !emoji_str.is_ascii() {
+                Ok(None)
+            } else {
+                Err("That doesn't look like an emoji, use a unicode emoji like ✅, or a custom emoji like <:name:id>".into())
+            }
+        }
+    }
+}
+
+/// Attempt to find role in cache,
+/// if found, return the name of the role,
+/// if not found, return a mention of the role.
+async fn role_name_or_id(ctx: &Context, role_id: RoleId) -> String {
+    match role_id.to_role_cached(&ctx).await {
+        Some(role) => role.name,
+        None => format!("<@&{}>", role_id.0),
+    }
+}
+
+/// Sends `content` to a channel, splitting it on line breaks
+/// so that no single message goes over discord's 2000 character limit
+pub(crate) async fn say_chunked(
+    ctx: &Context,
+    channel_id: ChannelId,
+    content: &str,
+) -> CommandResult {
+    let mut chunk = String::new();
+    for line in content.lines() {
+        if chunk.len() + line.len() + 1 > 2000 {
+            channel_id.say(&ctx.http, &chunk).await?;
+            chunk.clear();
+        }
+        chunk.push_str(line);
+        chunk.push('\n');
+    }
+    if !chunk.trim().is_empty() {
+        channel_id.say(&ctx.http, &chunk).await?;
     }
 
+    Ok(())
+}
+
+/// Asks the author of `msg` to confirm something, returning whether they replied `yes` in time
+pub(crate) async fn confirm(
+    ctx: &Context,
+    msg: &Message,
+    prompt: &str,
+) -> Result<bool, CommandError> {
     msg.channel_id
         .say(
             &ctx.http,
-            format!("Changed the server's prefix to ``{}``", args),
+            format!(
+                "{}\nReply `yes` within {} seconds to go ahead.",
+                prompt,
+                CONFIRM_TIMEOUT.as_secs()
+            ),
         )
-        .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(()))
+        .await?;
+
+    let reply = msg
+        .author
+        .await_reply(&ctx)
+        .channel_id(msg.channel_id)
+        .timeout(CONFIRM_TIMEOUT)
+        .await;
+    let confirmed = reply.map_or(false, |reply| {
+        reply.content.trim().eq_ignore_ascii_case("yes")
+    });
+    if !confirmed {
+        msg.channel_id
+            .say(&ctx.http, "Cancelled, nothing was changed.")
+            .await?;
+    }
+
+    Ok(confirmed)
+}
+
+/// Checks that the bot is able to give out `role_id` through reactions on a message in `channel_id`,
+/// returning an explanation of what's wrong if it can't.
+pub(crate) async fn check_reaction_role(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    role_id: RoleId,
+) -> Result<(), String> {
+    let bot_id = ctx.cache.current_user_id().await;
+    let guild = guild_id
+        .to_guild_cached(&ctx)
+        .await
+        .ok_or("I couldn't find this server in my cache, try again in a bit.")?;
+
+    if role_id.0 == guild_id.0 {
+        return Err("everyone already has @everyone, so it can't be given out.".to_owned());
+    }
+    let role = guild
+        .roles
+        .get(&role_id)
+        .ok_or("that role doesn't exist in this server.")?;
+    if role.managed {
+        return Err(format!(
+            "`{}` is managed by an integration (like a bot or server boosting), so only discord can give it out.",
+            role.name
+        ));
+    }
+
+    if !guild.channels.contains_key(&channel_id) {
+        return Err("that message isn't in this server.".to_owned());
+    }
+
+    let bot_member = guild
+        .members
+        .get(&bot_id)
+        .ok_or("I couldn't find myself in this server's member list, try again in a bit.")?;
+    let bot_top_position = bot_member
+        .roles
+        .iter()
+        .filter_map(|role_id| guild.roles.get(role_id))
+        .map(|role| role.position)
+        .max()
+        .unwrap_or(0);
+    if role.position >= bot_top_position {
+        return Err(format!(
+            "`{}` is higher than (or the same as) my highest role, move my role above it in the server settings.",
+            role.name
+        ));
+    }
+
+    if !guild.member_permissions(bot_id).manage_roles() {
+        return Err("I need the Manage Roles permission to give out roles.".to_owned());
+    }
+    if !guild.permissions_in(channel_id, bot_id).add_reactions() {
+        return Err(format!(
+            "I need the Add Reactions permission in <#{}> to add reactions to the message.",
+            channel_id.0
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that the bot can react with a custom emoji, which it can only do with emojis
+/// from servers it's in, returning an explanation of what's wrong if it can't.
+pub(crate) async fn check_custom_emoji(
+    ctx: &Context,
+    emoji: &EmojiIdentifier,
+) -> Result<(), String> {
+    let bot_id = ctx.cache.current_user_id().await;
+
+    for guild_id in ctx.cache.guilds().await {
+        // Emojis restricted to certain roles can only be used by members with one of them
+        let usable = ctx
+            .cache
+            .guild_field(guild_id, |guild| {
+                guild.emojis.get(&emoji.id).map(|guild_emoji| {
+                    guild_emoji.roles.is_empty()
+                        || guild.members.get(&bot_id).map_or(false, |member| {
+                            member
+                                .roles
+                                .iter()
+                                .any(|role_id| guild_emoji.roles.contains(role_id))
+                        })
+                })
+            })
+            .await
+            .flatten();
+        match usable {
+            Some(true) => return Ok(()),
+            Some(false) => {
+                return Err(format!(
+                    "`{}` can only be used by certain roles, and I don't have any of them.",
+                    emoji.name
+                ))
+            }
+            None => {}
+        }
+    }
+
+    Err(format!(
+        "`{}` is from a server I'm not in, so I can't react with it. Use a unicode emoji, or one from a server I'm in.",
+        emoji.name
+    ))
+}
+
+/// Explains why the bot couldn't add a reaction role's reaction to its message
+fn reaction_failure_reason(error: &serenity::Error) -> String {
+    if let serenity::Error::Http(http_error) = error {
+        if let HttpError::UnsuccessfulRequest(response) = http_error.as_ref() {
+            return match response.error.code {
+                10008 => "the message doesn't exist anymore.".to_owned(),
+                10014 => "discord doesn't know that emoji, it might have been deleted.".to_owned(),
+                30010 => "the message already has as many different reactions as discord allows."
+                    .to_owned(),
+                50001 | 50013 => {
+                    "I need the Read Message History and Add Reactions permissions in that channel."
+                        .to_owned()
+                }
+                _ => response.error.message.clone(),
+            };
+        }
+    }
+    error.to_string()
+}
+
+/// Optional settings that can be given after the message url when adding a reaction role
+#[derive(Default)]
+struct BindingOptions {
+    /// Reaction roles in the same exclusive group only let a user have one of their roles at a time
+    exclusive_group: Option<String>,
+    /// What adding and removing the reaction does
+    mode: ReactionMode,
+    /// Shown next to the role on panels
+    description: Option<String>,
+    /// A role members need to already have, before the reaction role will give them anything
+    required_role_id: Option<RoleId>,
+}
+
+/// Parses the options left over in `args` after a reaction role's emoji, role, and message.
+///
+/// `exclusive` makes every reaction role on the message part of one group,
+/// while `group=<name>` makes a named group that can span several messages.
+/// `mode=<mode>` sets the reaction role's [`ReactionMode`],
+/// `requires=<role>` sets a role members need before they can get this one,
+/// and `description=<text>` sets what panels show next to the role,
+/// taking up the rest of the arguments.
+fn parse_binding_options(
+    args: &mut Args,
+    message_id: MessageId,
+) -> Result<BindingOptions, CommandError> {
+    let mut options = BindingOptions::default();
+
+    while !args.is_empty() {
+        let option = args.single::<String>()?;
+        let (key, value) = match option.find('=') {
+            Some(index) => (&option[..index], Some(&option[index + 1..])),
+            None => (option.as_str(), None),
+        };
+
+        match (key.to_lowercase().as_str(), value) {
+            ("exclusive", None) => options.exclusive_group = Some(message_id.0.to_string()),
+            ("group", Some(name)) => {
+                let name = name.trim().to_lowercase();
+                // Purely numeric names are reserved for the per message groups `exclusive` makes.
+                if name.is_empty() || name.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(format!(
+                        "`{}` isn't a valid group name, it needs to contain at least one letter",
+                        name
+                    )
+                    .into());
+                }
+                options.exclusive_group = Some(name);
+            }
+            ("mode", Some(mode)) => options.mode = mode.parse::<ReactionMode>()?,
+            ("requires", Some(role)) => {
+                let role_id = match role.parse::<u64>() {
+                    Ok(role_id) => role_id,
+                    Err(_) => serenity::utils::parse_role(role).ok_or("Couldn't parse role id")?,
+                };
+                options.required_role_id = Some(RoleId(role_id));
+            }
+            // The description is free text, so it takes up the rest of the arguments.
+            ("description", Some(text)) => {
+                let description = format!("{} {}", text, args.rest());
+                options.description = Some(description.trim().to_owned());
+                break;
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown option `{}`, valid options are `exclusive`, `group=<name>`, `mode=<mode>`, `requires=<role>` and `description=<text>`",
+                    option
+                )
+                .into())
+            }
+        }
+    }
+
+    Ok(options)
 }
 
 #[command]
@@ -133,42 +670,42 @@ fn prefix(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
 #[required_permissions(ADMINISTRATOR)]
 /// Allows you to add a reaction to a message, that a user
 /// can click on to give them the role you specified.
-/// If you already added a role, this command will update
-/// to the role to use either the new emoji or on a different message.
+/// The same role can be added to several messages, and adding
+/// more than one role to the same emoji will give all of them at once.
+///
+/// Adding `exclusive` at the end makes the message "pick one", so
+/// picking a role takes away any other role from the message.
+/// `group=<name>` does the same, but for every reaction role in the named group,
+/// no matter which message it's on.
+///
+/// `mode=<mode>` changes what reacting does:
+/// `toggle` (the default) gives the role on reacting, and takes it away on unreacting,
+/// `add_only` only ever gives the role, for things like accepting the rules,
+/// `remove_only` takes the role away on reacting, for opting out of something,
+/// `verify` gives the role, then removes the reaction to keep the message clean.
+///
+/// The role has to be below the bot's highest role, and can't be @everyone
+/// or a role managed by an integration. The bot also needs the Manage Roles
+/// permission, and Add Reactions in the message's channel.
+/// Custom emojis have to be from a server the bot is in, so it can react with them.
+///
+/// `requires=<role>` only lets members that already have that role get this one,
+/// see reaction_rules to limit how many roles a member can get from the message.
+///
+/// If the message is a panel, `description=<text>` is shown next to the role,
+/// and has to be the last option given.
 ///
 /// Example usage:
-/// a.reaction_add <emoji> <role_id> <message_url>
-/// a.reaction_add :blue_heart: 401927402072309760 https://discordapp.com/channels/197169999494774784/355889026726887426/703682563398697000
+/// reaction_add <emoji> <role_id> <message_url> [exclusive | group=<name>] [mode=<mode>] [requires=<role>] [description=<text>]
+/// reaction_add :blue_heart: 401927402072309760 https://discordapp.com/channels/197169999494774784/355889026726887426/703682563398697000
+/// reaction_add :blue_heart: 401927402072309760 https://discordapp.com/channels/197169999494774784/355889026726887426/703682563398697000 group=colours
+/// reaction_add ✅ 401927402072309760 https://discordapp.com/channels/197169999494774784/355889026726887426/703682563398697000 mode=verify
 ///
 /// Restricted to Users with the Administrator permission
-fn reaction_add(ctx: &mut Context, msg: &Message, mut args: Args) -> CommandResult {
+async fn reaction_add(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let emoji_string = args.single::<String>()?;
     let emoji_str: &str = emoji_string.as_ref();
-    let emoji = {
-        match serenity::utils::parse_emoji(emoji_str) {
-            Some(emoji) => Some(emoji),
-            None => {
-                if emoji_str.starts_with("<a:") {
-                    let mut split = emoji_str.split(':');
-                    let name = split.nth(1).ok_or("Failed to parse emoji")?;
-                    log::debug!("emoji name: {}", name);
-                    let id = split
-                        .next()
-                        .ok_or("Failed to get name of emoji")?
-                        .trim_end_matches('>');
-                    log::debug!("emoji id: {}", id);
-                    Some(serenity::model::misc::EmojiIdentifier {
-                        id: serenity::model::id::EmojiId(id.parse::<u64>()?),
-                        name: name.to_string(),
-                    })
-                } else if !emoji_str.is_ascii() {
-                    None
-                } else {
-                    return Err(CommandError("Error parsing emoji".to_string()));
-                }
-            }
-        }
-    };
+    let emoji = parse_emoji_arg(emoji_str)?;
     let role_id = {
         match args.single::<RoleId>() {
             Ok(role_id) => role_id,
@@ -179,98 +716,718 @@ fn reaction_add(ctx: &mut Context, msg: &Message, mut args: Args) -> CommandResu
         }
     };
 
-    let message = args.single::<String>()?;
-    // The lazy_static will only panic if the regex itself is invalid, and therefore, shouldn't
-    // ever panic.
-    // regex test link: https://regex101.com/r/Rth5jE/6
-    // rust playground link: https://play.rust-lang.org/?version=stable&mode=debug&edition=2018&gist=7bc300a4af839a35ec3a9c4daf9344da
-    lazy_static! {
-        static ref REGEX: Regex = Regex::new(
-            r"(?m)http[s]?://?(?:ptb\.|canary\.)?discord(?:app)?\.com/channels/\d*/(\d*)/(\d*)",
+    let (channel_id, message_id) = parse_message_url(&args.single::<String>()?)?;
+    let options = parse_binding_options(&mut args, message_id)?;
+    let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
+
+    // Catch anything that would stop the role from being given out now,
+    // rather than it only showing up in the logs once someone reacts.
+    if let Err(reason) = check_reaction_role(ctx, guild_id, channel_id, role_id).await {
+        msg.channel_id
+            .say(
+                &ctx.http,
+                format!("I can't use that role for a reaction role: {}", reason),
+            )
+            .await?;
+        return Ok(());
+    }
+    // A prerequisite that doesn't exist would reject everyone that reacts.
+    if let Some(required_role_id) = options.required_role_id {
+        let exists = ctx
+            .cache
+            .guild_field(guild_id, |guild| {
+                guild.roles.contains_key(&required_role_id)
+            })
+            .await
+            .unwrap_or(false);
+        if !exists {
+            msg.channel_id
+                .say(
+                    &ctx.http,
+                    "The role given with `requires=` doesn't exist in this server.",
+                )
+                .await?;
+            return Ok(());
+        }
+    }
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
+    };
+
+    if let Some(emoji_indentifier) = &emoji {
+        if let Err(reason) = check_custom_emoji(ctx, emoji_indentifier).await {
+            msg.channel_id
+                .say(
+                    &ctx.http,
+                    format!("I can't use that emoji for a reaction role: {}", reason),
+                )
+                .await?;
+            return Ok(());
+        }
+    }
+
+    // The reaction role is only kept if its reaction can be added to the message,
+    // otherwise the transaction is dropped, which rolls it back.
+    let mut transaction = fancy_db.pool().begin().await?;
+    let reaction_result = if let Some(emoji_indentifier) = emoji {
+        log::debug!(
+            "Custom Emoji: {}:{}",
+            emoji_indentifier.name,
+            emoji_indentifier.id
+        );
+        sqlx::query!("INSERT INTO reaction_roles (guild_id, role_id, channel_id, message_id, emoji, emoji_id, name, exclusive_group, mode, description, required_role_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT (guild_id, message_id, emoji, role_id) DO UPDATE SET channel_id = $3, name = $7, exclusive_group = $8, mode = $9, description = $10, required_role_id = $11",
+            guild_id.0 as i64,
+            role_id.0 as i64,
+            channel_id.0 as i64,
+            message_id.0 as i64,
+            emoji_indentifier.id.0.to_string(),
+            emoji_indentifier.id.0 as i64,
+            emoji_indentifier.name,
+            options.exclusive_group,
+            options.mode.as_str(),
+            options.description,
+            options.required_role_id.map(|role_id| role_id.0 as i64)
+        )
+        .execute(&mut transaction)
+        .await?;
+        ctx.http
+            .create_reaction(channel_id.0, message_id.0, &emoji_indentifier.into())
+            .await
+    } else {
+        log::debug!("Unicode emoji: {}", emoji_str);
+        sqlx::query!("INSERT INTO reaction_roles (guild_id, role_id, channel_id, message_id, emoji, name, exclusive_group, mode, description, required_role_id) VALUES ($1, $2, $3, $4, $5, $5, $6, $7, $8, $9) ON CONFLICT (guild_id, message_id, emoji, role_id) DO UPDATE SET channel_id = $3, exclusive_group = $6, mode = $7, description = $8, required_role_id = $9",
+            guild_id.0 as i64,
+            role_id.0 as i64,
+            channel_id.0 as i64,
+            message_id.0 as i64,
+            emoji_str,
+            options.exclusive_group,
+            options.mode.as_str(),
+            options.description,
+            options.required_role_id.map(|role_id| role_id.0 as i64)
         )
-        .expect("Invalid regex, this should never happen.");
+        .execute(&mut transaction)
+        .await?;
+        ctx.http
+            .create_reaction(
+                channel_id.0,
+                message_id.0,
+                &ReactionType::Unicode(emoji_str.to_owned()),
+            )
+            .await
+    };
+
+    if let Err(e) = reaction_result {
+        drop(transaction);
+        msg.channel_id
+            .say(
+                &ctx.http,
+                format!(
+                    "I couldn't add the reaction to the message, so the reaction role wasn't saved: {}",
+                    reaction_failure_reason(&e)
+                ),
+            )
+            .await?;
+        return Ok(());
+    }
+    transaction.commit().await?;
+
+    refresh_reaction_messages(ctx, &[message_id]).await;
+    refresh_panel(ctx, guild_id, message_id).await?;
+
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Successfully added the role `{}`, with the emoji {}, to the message:\n{}",
+                role_name_or_id(ctx, role_id).await,
+                emoji_str,
+                message_url(guild_id.0, channel_id.0, message_id.0)
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[aliases("reaction_roles")]
+#[required_permissions(ADMINISTRATOR)]
+/// Lists every reaction role set up in this server,
+/// grouped by the message they're on.
+///
+/// Restricted to Users with the Administrator permission
+async fn reaction_list(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
+    };
+
+    let bindings = sqlx::query!(
+        "SELECT channel_id, message_id, role_id, name, emoji_id, exclusive_group, mode, required_role_id FROM reaction_roles WHERE guild_id = $1 ORDER BY message_id, name",
+        guild_id.0 as i64
+    )
+    .fetch_all(fancy_db.pool())
+    .await?;
+
+    if bindings.is_empty() {
+        msg.channel_id
+            .say(
+                &ctx.http,
+                "There aren't any reaction roles in this server yet.",
+            )
+            .await?;
+        return Ok(());
     }
-    let capture = REGEX.captures(&message).ok_or("Couldn't find message id")?;
-    let message_id = {
-        let id = capture
-            .get(2)
-            .ok_or("Couldn't get messsage id")?
-            .as_str()
-            .parse::<u64>()?;
 
-        MessageId(id)
+    let mut response = String::from("**Reaction roles in this server:**\n");
+    let mut current_message = None;
+    for binding in bindings {
+        if current_message != Some(binding.message_id) {
+            current_message = Some(binding.message_id);
+            let location = match binding.channel_id {
+                Some(channel_id) => {
+                    message_url(guild_id.0, channel_id as u64, binding.message_id as u64)
+                }
+                None => format!("Message {} (unknown channel)", binding.message_id),
+            };
+            response.push_str(&format!("\n{}\n", location));
+        }
+        response.push_str(&format!(
+            "{} → `{}`",
+            emoji_display(&binding.name, binding.emoji_id),
+            role_name_or_id(ctx, RoleId(binding.role_id as u64)).await
+        ));
+        match binding.exclusive_group {
+            Some(ref group) if *group == binding.message_id.to_string() => {
+                response.push_str(" (exclusive)")
+            }
+            Some(ref group) => response.push_str(&format!(" (exclusive group `{}`)", group)),
+            None => {}
+        }
+        if binding.mode != ReactionMode::Toggle.as_str() {
+            response.push_str(&format!(" [{}]", binding.mode));
+        }
+        if let Some(required_role_id) = binding.required_role_id {
+            response.push_str(&format!(
+                " (requires `{}`)",
+                role_name_or_id(ctx, RoleId(required_role_id as u64)).await
+            ));
+        }
+        response.push('\n');
+    }
+
+    say_chunked(ctx, msg.channel_id, &response).await
+}
+
+#[command]
+#[min_args(2)]
+#[required_permissions(ADMINISTRATOR)]
+/// Removes a reaction role from a message, along with the bot's own reaction.
+/// If the emoji gives more than one role, a role can be provided
+/// to only remove that role from the emoji.
+///
+/// Example usage:
+/// reaction_remove <message_url> <emoji> [role_id]
+/// reaction_remove https://discordapp.com/channels/197169999494774784/355889026726887426/703682563398697000 :blue_heart:
+///
+/// Restricted to Users with the Administrator permission
+async fn reaction_remove(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (channel_id, message_id) = parse_message_url(&args.single::<String>()?)?;
+    let emoji_string = args.single::<String>()?;
+    let (emoji, reaction_type) = match parse_emoji_arg(&emoji_string)? {
+        Some(emoji_indentifier) => (
+            emoji_indentifier.id.0.to_string(),
+            ReactionType::from(emoji_indentifier),
+        ),
+        None => (
+            emoji_string.clone(),
+            ReactionType::Unicode(emoji_string.clone()),
+        ),
+    };
+    let role_id = if args.is_empty() {
+        None
+    } else {
+        match args.single::<RoleId>() {
+            Ok(role_id) => Some(role_id),
+            Err(_) => Some(RoleId(
+                serenity::utils::parse_role(args.single::<String>()?)
+                    .ok_or("Couldn't parse role id")?,
+            )),
+        }
     };
     let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
 
-    let (fancy_db, runtime_lock) = {
-        let data = ctx.data.read();
-        let fancy_db = Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?);
-        let runtime_lock = Arc::clone(
-            data.get::<TokioContainer>()
-                .ok_or("Couldn't get runtime lock")?,
-        );
-        (fancy_db, runtime_lock)
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
     };
 
-    {
-        let mut tokio = runtime_lock.try_lock()?;
-        if let Some(emoji_indentifier) = emoji {
-            log::debug!(
-                "Custom Emoji: {}:{}",
-                emoji_indentifier.name,
-                emoji_indentifier.id
-            );
-            tokio.block_on(
-                sqlx::query!("INSERT INTO reaction_roles (guild_id, role_id, message_id, emoji_id, name) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (role_id) DO UPDATE SET guild_id = $1, role_id = $2, message_id = $3, emoji_id = $4, name = $5",
-                        guild_id.0 as i64,
-                        role_id.0 as i64,
-                        message_id.0 as i64,
-                        emoji_indentifier.id.0 as i64,
-                        emoji_indentifier.name
-                    ).execute(fancy_db.pool())
-            )?;
-            ctx.http.create_reaction(
-                capture
-                    .get(1)
-                    .ok_or("Failed to get channel_id to add reaction to the linked message")?
-                    .as_str()
-                    .parse::<u64>()?,
-                message_id.0,
-                &emoji_indentifier.into(),
-            )?;
-        } else {
-            log::debug!("Unicode emoji: {}", emoji_str);
-            tokio.block_on(sqlx::query!("INSERT INTO reaction_roles (guild_id, role_id, message_id, name) VALUES ($1, $2, $3, $4) ON CONFLICT (role_id) DO UPDATE SET guild_id = $1, role_id = $2, message_id = $3, name = $4",
-                        guild_id.0 as i64,
-                        role_id.0 as i64,
-                        message_id.0 as i64,
-                        emoji_str
-                    ).execute(fancy_db.pool())
-            )?;
-            ctx.http.create_reaction(
-                capture
-                    .get(1)
-                    .ok_or("Failed to get channel_id to add reaction to the linked message")?
-                    .as_str()
-                    .parse::<u64>()?,
-                message_id.0,
-                &emoji_str.into(),
-            )?;
+    let removed = match role_id {
+        Some(role_id) => {
+            sqlx::query!(
+                "DELETE FROM reaction_roles WHERE guild_id = $1 AND message_id = $2 AND emoji = $3 AND role_id = $4",
+                guild_id.0 as i64,
+                message_id.0 as i64,
+                emoji,
+                role_id.0 as i64
+            )
+            .execute(fancy_db.pool())
+            .await?
         }
+        None => {
+            sqlx::query!(
+                "DELETE FROM reaction_roles WHERE guild_id = $1 AND message_id = $2 AND emoji = $3",
+                guild_id.0 as i64,
+                message_id.0 as i64,
+                emoji
+            )
+            .execute(fancy_db.pool())
+            .await?
+        }
+    };
+    let remaining = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM reaction_roles WHERE guild_id = $1 AND message_id = $2 AND emoji = $3",
+        guild_id.0 as i64,
+        message_id.0 as i64,
+        emoji
+    )
+    .fetch_one(fancy_db.pool())
+    .await?
+    .count;
+
+    if removed == 0 {
+        msg.channel_id
+            .say(
+                &ctx.http,
+                format!(
+                    "There's no matching reaction role using {} on that message.",
+                    emoji_string
+                ),
+            )
+            .await?;
+        return Ok(());
     }
 
-    // Attempt to find role in cache,
-    // if found, return the name of the role,
-    // if not found, return a mention of the role.
-    let role_name_or_id = {
-        match role_id.to_role_cached(&ctx) {
-            Some(role) => role.name,
-            None => format!("<@&{}>", role_id.0),
+    // Only take our own reaction off once nothing on the message uses the emoji anymore.
+    // The binding is already gone at this point, so failing to clean up
+    // our own reaction isn't worth failing the whole command over.
+    if remaining.unwrap_or(0) == 0 {
+        if let Err(e) = ctx
+            .http
+            .delete_reaction(channel_id.0, message_id.0, None, &reaction_type)
+            .await
+        {
+            warn!("Failed to remove own reaction from {}: {}", message_id, e);
         }
+    }
+
+    refresh_reaction_messages(ctx, &[message_id]).await;
+    refresh_panel(ctx, guild_id, message_id).await?;
+
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Removed {} reaction role(s) using {} from the message:\n{}",
+                removed,
+                emoji_string,
+                message_url(guild_id.0, channel_id.0, message_id.0)
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[min_args(1)]
+#[required_permissions(ADMINISTRATOR)]
+/// Removes every reaction role from a message, along with the bot's own reactions.
+///
+/// Example usage:
+/// reaction_clear <message_url>
+///
+/// Restricted to Users with the Administrator permission
+async fn reaction_clear(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (channel_id, message_id) = parse_message_url(&args.single::<String>()?)?;
+    let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
     };
 
-    msg
+    let removed = sqlx::query!(
+        "DELETE FROM reaction_roles WHERE guild_id = $1 AND message_id = $2 RETURNING emoji, name, emoji_id",
+        guild_id.0 as i64,
+        message_id.0 as i64
+    )
+    .fetch_all(fancy_db.pool())
+    .await?;
+
+    if removed.is_empty() {
+        msg.channel_id
+            .say(
+                &ctx.http,
+                "There aren't any reaction roles on that message.",
+            )
+            .await?;
+        return Ok(());
+    }
+
+    // Several roles can share an emoji, but we only ever have one reaction per emoji to remove.
+    let mut cleared_emojis = HashSet::new();
+    for binding in removed
+        .iter()
+        .filter(|b| cleared_emojis.insert(b.emoji.clone()))
+    {
+        let reaction_type = reaction_type_from_row(&binding.name, binding.emoji_id);
+        if let Err(e) = ctx
+            .http
+            .delete_reaction(channel_id.0, message_id.0, None, &reaction_type)
+            .await
+        {
+            warn!("Failed to remove own reaction from {}: {}", message_id, e);
+        }
+    }
+
+    refresh_reaction_messages(ctx, &[message_id]).await;
+    refresh_panel(ctx, guild_id, message_id).await?;
+
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Removed {} reaction role(s) from the message:\n{}",
+                removed.len(),
+                message_url(guild_id.0, channel_id.0, message_id.0)
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[required_permissions(ADMINISTRATOR)]
+/// Goes through every reaction role in this server, giving and taking away roles
+/// so they match who has reacted. This already happens whenever the bot starts,
+/// but can be used if roles have gotten out of sync some other way.
+///
+/// Members that have a role given out by a toggle reaction role, without having
+/// reacted for it, will have the role taken away. Reactions that break their
+/// message's rules don't give out anything.
+///
+/// Restricted to Users with the Administrator permission
+async fn reaction_sync(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
+
+    let mut message = msg
         .channel_id
-        .say(&ctx.http, format!("Successfully added the role `{}`, with the emoji {}, to the message:\nhttps://discordapp.com/channels/{}/{}/{}", role_name_or_id, emoji_str, guild_id.0, msg.channel_id.0, message_id))
-        .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(()))
+        .say(
+            &ctx.http,
+            "Syncing reaction roles, this might take a while....",
+        )
+        .await?;
+
+    let report = sync_reaction_roles(ctx, &[guild_id]).await?;
+
+    message
+        .edit(&ctx, |m| {
+            m.content(format!(
+                "Synced reaction roles on {} message(s), {} role(s) will be added and {} role(s) removed.{}{}",
+                report.messages,
+                report.roles_added,
+                report.roles_removed,
+                if report.rejected > 0 {
+                    format!(
+                        "\n{} reaction(s) broke their message's rules, so didn't give out any roles.",
+                        report.rejected
+                    )
+                } else {
+                    String::new()
+                },
+                if report.failures > 0 {
+                    format!(
+                        "\n{} thing(s) couldn't be synced, make sure I can see every reaction role message and manage their roles.",
+                        report.failures
+                    )
+                } else {
+                    String::new()
+                }
+            ))
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[min_args(1)]
+#[required_permissions(ADMINISTRATOR)]
+/// Sets the rules for the reaction roles on a message.
+/// `max=<number>` limits how many of the message's roles a member can have at once,
+/// and `max=none` removes the limit.
+/// `dm=yes` lets members know why their reaction was removed when it breaks a rule,
+/// whether that's this limit or a role's prerequisite set with reaction_add's `requires=<role>`.
+/// Without any rules, it shows the message's current rules.
+///
+/// Example usage:
+/// reaction_rules <message_url> [max=<number> | max=none] [dm=yes | dm=no]
+/// reaction_rules https://discordapp.com/channels/197169999494774784/355889026726887426/703682563398697000 max=3 dm=yes
+///
+/// Restricted to Users with the Administrator permission
+async fn reaction_rules(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (channel_id, message_id) = parse_message_url(&args.single::<String>()?)?;
+    let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
+    let in_guild = channel_id
+        .to_channel_cached(&ctx)
+        .await
+        .and_then(|channel| channel.guild())
+        .map_or(false, |channel| channel.guild_id == guild_id);
+    if !in_guild {
+        return Err("That message isn't in this server".into());
+    }
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
+    };
+
+    let current = sqlx::query!(
+        "SELECT max_roles, dm_on_reject FROM reaction_role_rules WHERE guild_id = $1 AND message_id = $2",
+        guild_id.0 as i64,
+        message_id.0 as i64
+    )
+    .fetch_optional(fancy_db.pool())
+    .await?;
+    let (mut max_roles, mut dm_on_reject) =
+        current.map_or((None, false), |rules| (rules.max_roles, rules.dm_on_reject));
+
+    if !args.is_empty() {
+        while !args.is_empty() {
+            let rule = args.single::<String>()?;
+            let (key, value) = match rule.find('=') {
+                Some(index) => (&rule[..index], &rule[index + 1..]),
+                None => (rule.as_str(), ""),
+            };
+            match (key.to_lowercase().as_str(), value.to_lowercase().as_str()) {
+                ("max", "none") => max_roles = None,
+                ("max", number) => {
+                    let number = number
+                        .parse::<i32>()
+                        .ok()
+                        .filter(|number| *number > 0)
+                        .ok_or("The role limit has to be a number above 0, or `none`")?;
+                    max_roles = Some(number);
+                }
+                ("dm", "yes") | ("dm", "on") | ("dm", "true") => dm_on_reject = true,
+                ("dm", "no") | ("dm", "off") | ("dm", "false") => dm_on_reject = false,
+                _ => {
+                    return Err(format!(
+                        "Unknown rule `{}`, valid rules are `max=<number>`, `max=none`, `dm=yes` and `dm=no`",
+                        rule
+                    )
+                    .into())
+                }
+            }
+        }
+
+        sqlx::query!(
+            "INSERT INTO reaction_role_rules (message_id, guild_id, channel_id, max_roles, dm_on_reject) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (message_id) DO UPDATE SET max_roles = $4, dm_on_reject = $5 WHERE reaction_role_rules.guild_id = EXCLUDED.guild_id",
+            message_id.0 as i64,
+            guild_id.0 as i64,
+            channel_id.0 as i64,
+            max_roles,
+            dm_on_reject
+        )
+        .execute(fancy_db.pool())
+        .await?;
+        refresh_reaction_messages(ctx, &[message_id]).await;
+    }
+
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Rules for {}\nRole limit: {}\nDM members when their reaction is removed: {}",
+                message_url(guild_id.0, channel_id.0, message_id.0),
+                max_roles.map_or_else(|| "none".to_owned(), |max| max.to_string()),
+                if dm_on_reject { "yes" } else { "no" }
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[required_permissions(ADMINISTRATOR)]
+/// Sets the channel the bot reports changes to the server's reaction roles in,
+/// like the ones it removes when their role, emoji or message is deleted,
+/// along with every role members pick up or drop through reactions,
+/// reactions that were rejected and role changes that failed.
+/// `none` stops the reports, and without a channel it shows the current one.
+///
+/// Example usage:
+/// log_channel [#channel | none]
+/// log_channel #bot-logs
+///
+/// Restricted to Users with the Administrator permission
+async fn log_channel(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
+    };
+
+    if args.is_empty() {
+        let log_channel_id = sqlx::query!(
+            "SELECT log_channel_id FROM guild WHERE id = $1",
+            guild_id.0 as i64
+        )
+        .fetch_optional(fancy_db.pool())
+        .await?
+        .and_then(|guild| guild.log_channel_id);
+        let reply = match log_channel_id {
+            Some(channel_id) => format!("This server's log channel is <#{}>", channel_id),
+            None => "This server doesn't have a log channel".to_owned(),
+        };
+        msg.channel_id.say(&ctx.http, reply).await?;
+        return Ok(());
+    }
+
+    let channel_arg = args.rest().trim();
+    let log_channel_id = if channel_arg.eq_ignore_ascii_case("none") {
+        None
+    } else {
+        let channel_id = parse_channel(channel_arg)
+            .or_else(|| channel_arg.parse::<u64>().ok())
+            .map(ChannelId)
+            .ok_or("That isn't a channel, mention one like #bot-logs, or use `none`")?;
+        let in_guild = channel_id
+            .to_channel_cached(&ctx)
+            .await
+            .and_then(|channel| channel.guild())
+            .map_or(false, |channel| channel.guild_id == guild_id);
+        if !in_guild {
+            return Err("That channel isn't in this server".into());
+        }
+        Some(channel_id)
+    };
+
+    let guild_name = msg.guild(&ctx).await.ok_or("Failed to get guild")?.name;
+
+    sqlx::query!(
+        "INSERT INTO guild (id, name, log_channel_id) VALUES ($1, $2, $3) ON CONFLICT (id) DO UPDATE SET name = $2, log_channel_id = $3",
+        guild_id.0 as i64,
+        guild_name,
+        log_channel_id.map(|channel_id| channel_id.0 as i64)
+    )
+    .execute(fancy_db.pool())
+    .await?;
+
+    let reply = match log_channel_id {
+        Some(channel_id) => format!(
+            "Reaction role changes will be reported in {}",
+            channel_id.mention()
+        ),
+        None => "Reaction role changes won't be reported anymore".to_owned(),
+    };
+    msg.channel_id.say(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[required_permissions(ADMINISTRATOR)]
+/// Shows the role changes from reaction roles that are still waiting to be made,
+/// and the ones that failed for good, like when the bot is missing permissions.
+/// `clear` forgets about the failed ones, once they've been dealt with.
+///
+/// Example usage:
+/// role_queue [clear]
+///
+/// Restricted to Users with the Administrator permission
+async fn role_queue(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
+    };
+
+    if args.rest().trim().eq_ignore_ascii_case("clear") {
+        let cleared = sqlx::query!(
+            "DELETE FROM role_queue WHERE guild_id = $1 AND status = 'failed'",
+            guild_id.0 as i64
+        )
+        .execute(fancy_db.pool())
+        .await?;
+        msg.channel_id
+            .say(
+                &ctx.http,
+                format!("Cleared {} failed role change(s)", cleared),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let changes = sqlx::query!(
+        "SELECT user_id, role_id, action, status, attempts, last_error, CAST(EXTRACT(EPOCH FROM next_attempt_at - now()) AS bigint) AS retry_in
+         FROM role_queue WHERE guild_id = $1 ORDER BY status, next_attempt_at LIMIT 50",
+        guild_id.0 as i64
+    )
+    .fetch_all(fancy_db.pool())
+    .await?;
+
+    if changes.is_empty() {
+        msg.channel_id
+            .say(
+                &ctx.http,
+                "There aren't any role changes waiting to be made.",
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let mut response = String::from("**Role changes waiting to be made, or that failed:**\n");
+    for change in changes {
+        let user_id = UserId(change.user_id as u64);
+        let user = user_id
+            .to_user_cached(&ctx)
+            .await
+            .map_or_else(|| user_id.0.to_string(), |user| user.tag());
+        response.push_str(&format!(
+            "{} `{}` for `{}`",
+            change.action,
+            role_name_or_id(ctx, RoleId(change.role_id as u64)).await,
+            user
+        ));
+        if change.status == "failed" {
+            response.push_str(" **failed**");
+        } else if change.attempts > 0 {
+            response.push_str(&format!(
+                " retrying in {}s",
+                change.retry_in.unwrap_or(0).max(0)
+            ));
+        } else {
+            response.push_str(" pending");
+        }
+        if let Some(last_error) = change.last_error {
+            response.push_str(&format!(
+                " after {} attempt(s): {}",
+                change.attempts, last_error
+            ));
+        }
+        response.push('\n');
+    }
+
+    say_chunked(ctx, msg.channel_id, &response).await
 }
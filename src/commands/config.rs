@@ -0,0 +1,761 @@
+use crate::{
+    commands::admin::{
+        check_custom_emoji,
+        check_reaction_role,
+        confirm,
+        say_chunked,
+    },
+    core::{
+        guild_config::{
+            ConfigFormat,
+            GuildConfig,
+            NamedId,
+            PanelConfig,
+            ReactionRoleConfig,
+            RulesConfig,
+        },
+        panels::refresh_panel,
+        prefixes::{
+            normalize_prefix,
+            reload_prefixes,
+            validate_prefix,
+            MAX_PREFIXES,
+        },
+        reaction_cache::refresh_reaction_messages,
+        structs::{
+            PoolContainer,
+            ReactionMode,
+        },
+        utils::{
+            emoji_display,
+            reaction_type_from_row,
+            FancyPool,
+        },
+    },
+};
+use log::warn;
+use serenity::{
+    framework::standard::{
+        macros::command,
+        Args,
+        CommandResult,
+    },
+    model::{
+        id::EmojiId,
+        misc::EmojiIdentifier,
+        prelude::{
+            ChannelId,
+            Guild,
+            Message,
+            MessageId,
+            RoleId,
+        },
+    },
+    prelude::Context,
+};
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+        HashMap,
+        HashSet,
+    },
+    sync::Arc,
+};
+
+/// Config files bigger than this aren't downloaded
+const MAX_CONFIG_SIZE: u64 = 1024 * 1024;
+
+#[command]
+#[required_permissions(ADMINISTRATOR)]
+/// Uploads a file with everything the bot has stored about this server:
+/// the prefixes, log channel, reaction roles, their rules, and panels.
+/// The file can be brought back with `config import`, here or in another server.
+/// The format is `toml` unless `json` is asked for.
+///
+/// Example usage:
+/// config export [toml | json]
+/// config export json
+///
+/// Restricted to Users with the Administrator permission
+async fn export(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild = msg.guild(&ctx).await.ok_or("Failed to get guild")?;
+    let format = if args.is_empty() {
+        ConfigFormat::Toml
+    } else {
+        args.rest().trim().parse::<ConfigFormat>()?
+    };
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
+    };
+
+    let config = GuildConfig::fetch(&fancy_db, &guild).await?;
+    let contents = config.serialize(format)?;
+    let filename = format!("{}-config.{}", guild.id.0, format.extension());
+
+    msg.channel_id
+        .send_files(
+            &ctx.http,
+            vec![(contents.as_bytes(), filename.as_str())],
+            |m| {
+                m.content(format!(
+                    "Exported {} reaction role(s), {} rule(s) and {} panel(s).",
+                    config.reaction_roles.len(),
+                    config.rules.len(),
+                    config.panels.len()
+                ))
+            },
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[required_permissions(ADMINISTRATOR)]
+/// Loads a file made by `config export`, attached to the message.
+/// Before anything is changed, the differences from this server's current setup are shown,
+/// and have to be confirmed.
+///
+/// Roles are matched by their id, or by their name if the id isn't in this server,
+/// and channels the same way. Panels whose messages aren't in this server are posted again
+/// in the matching channel, along with their reaction roles. Anything else that can't be matched,
+/// like reaction roles on other messages that aren't in this server, is skipped and listed.
+/// Nothing already set up is removed, only added to or changed.
+///
+/// Example usage:
+/// config import (with the file attached)
+///
+/// Restricted to Users with the Administrator permission
+async fn import(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild = msg.guild(&ctx).await.ok_or("Failed to get guild")?;
+    let attachment = msg
+        .attachments
+        .first()
+        .ok_or("Attach a config file made by `config export` to the message")?;
+    if attachment.size > MAX_CONFIG_SIZE {
+        return Err("That file is too big to be a config".into());
+    }
+    let format = ConfigFormat::from_filename(&attachment.filename)
+        .ok_or("Config files have to end in `.toml` or `.json`")?;
+    let contents = String::from_utf8(attachment.download().await?)
+        .map_err(|_| "That file isn't text, so it can't be a config")?;
+    let config = GuildConfig::parse(&contents, format)?;
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
+    };
+
+    let current = GuildConfig::fetch(&fancy_db, &guild).await?;
+    let mut plan = plan_import(ctx, &guild, config, &current).await;
+
+    let mut report = String::new();
+    if !plan.skipped.is_empty() {
+        report.push_str(
+            "**These couldn't be matched to anything in this server, and will be skipped:**\n",
+        );
+        for line in &plan.skipped {
+            report.push_str(&format!("{}\n", line));
+        }
+        report.push('\n');
+    }
+    if plan.changes.is_empty() {
+        report.push_str("This server already matches the config, so there's nothing to import.");
+        return say_chunked(ctx, msg.channel_id, &report).await;
+    }
+    report.push_str("**Importing will make these changes:**\n");
+    for line in &plan.changes {
+        report.push_str(&format!("{}\n", line));
+    }
+    say_chunked(ctx, msg.channel_id, &report).await?;
+
+    if !confirm(ctx, msg, "Import the config?").await? {
+        return Ok(());
+    }
+
+    // Panels whose messages aren't in this server are posted again before anything is saved,
+    // so their reaction roles and rules can be moved over to the new messages.
+    let mut reposted: HashMap<u64, (ChannelId, MessageId)> = HashMap::new();
+    for panel in &plan.panels {
+        let channel_id = match plan.destinations.get(&panel.message_id) {
+            Some(Destination::Repost(channel_id)) => *channel_id,
+            _ => continue,
+        };
+        match channel_id
+            .send_message(&ctx.http, |m| m.embed(|e| e.title(&panel.title)))
+            .await
+        {
+            Ok(message) => {
+                reposted.insert(panel.message_id, (channel_id, message.id));
+            }
+            Err(e) => {
+                delete_reposted(ctx, &reposted).await;
+                return Err(format!(
+                    "I couldn't post the panel `{}` in <#{}>, so nothing was imported: {}",
+                    panel.title, channel_id.0, e
+                )
+                .into());
+            }
+        }
+    }
+    for binding in &mut plan.reaction_roles {
+        if let Some((channel_id, message_id)) = reposted.get(&binding.message_id) {
+            // Reaction roles made exclusive to the old message need to be exclusive to the new one instead
+            if binding.exclusive_group.as_deref() == Some(binding.message_id.to_string().as_str()) {
+                binding.exclusive_group = Some(message_id.0.to_string());
+            }
+            binding.channel_id = channel_id.0;
+            binding.message_id = message_id.0;
+        }
+    }
+    for rules in &mut plan.rules {
+        if let Some((channel_id, message_id)) = reposted.get(&rules.message_id) {
+            rules.channel_id = channel_id.0;
+            rules.message_id = message_id.0;
+        }
+    }
+    for panel in &mut plan.panels {
+        if let Some((channel_id, message_id)) = reposted.get(&panel.message_id) {
+            panel.channel_id = channel_id.0;
+            panel.message_id = message_id.0;
+        }
+    }
+
+    if let Err(e) = save_import(&fancy_db, &guild, &plan).await {
+        delete_reposted(ctx, &reposted).await;
+        return Err(e.into());
+    }
+
+    if plan.prefixes.is_some() {
+        reload_prefixes(ctx, guild.id).await?;
+    }
+
+    let message_ids: Vec<MessageId> = plan
+        .reaction_roles
+        .iter()
+        .map(|binding| binding.message_id)
+        .chain(plan.panels.iter().map(|panel| panel.message_id))
+        .chain(plan.rules.iter().map(|rules| rules.message_id))
+        .collect::<BTreeSet<u64>>()
+        .into_iter()
+        .map(MessageId)
+        .collect();
+    refresh_reaction_messages(ctx, &message_ids).await;
+    for message_id in &message_ids {
+        if let Err(e) = refresh_panel(ctx, guild.id, *message_id).await {
+            warn!(
+                "Failed to refresh panel {} after an import: {}",
+                message_id, e
+            );
+        }
+    }
+
+    // The reactions are most likely still on their messages, but a missing one would stop its role being given out.
+    // Reactions are shown in the order they're added, so reposted panels get them in the order they list them.
+    let mut missing_reactions = 0;
+    for binding in &plan.reaction_roles {
+        if let Err(e) = ctx
+            .http
+            .create_reaction(
+                binding.channel_id,
+                binding.message_id,
+                &reaction_type_from_row(&binding.name, binding.emoji_id.map(|id| id as i64)),
+            )
+            .await
+        {
+            warn!("Failed to add an imported reaction role's reaction: {}", e);
+            missing_reactions += 1;
+        }
+    }
+
+    let mut reply = format!(
+        "Imported the config, with {} reaction role(s), {} rule(s) and {} panel(s).",
+        plan.reaction_roles.len(),
+        plan.rules.len(),
+        plan.panels.len()
+    );
+    if !reposted.is_empty() {
+        reply.push_str(&format!(
+            "\n{} panel(s) weren't in this server, so I posted them again.",
+            reposted.len()
+        ));
+    }
+    if plan.skipped_bindings > 0 {
+        reply.push_str(&format!(
+            "\n{} reaction role(s) were skipped, the list above says why.",
+            plan.skipped_bindings
+        ));
+    }
+    if missing_reactions > 0 {
+        reply.push_str(&format!(
+            "\nI couldn't add {} of the reactions to their messages, add them by hand or they won't give out their roles.",
+            missing_reactions
+        ));
+    }
+    msg.channel_id.say(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+/// Deletes the panels an import posted again, when the import couldn't finish
+async fn delete_reposted(ctx: &Context, reposted: &HashMap<u64, (ChannelId, MessageId)>) {
+    for (channel_id, message_id) in reposted.values() {
+        if let Err(e) = channel_id.delete_message(&ctx.http, message_id).await {
+            warn!(
+                "Failed to delete panel {} after an import failed: {}",
+                message_id, e
+            );
+        }
+    }
+}
+
+/// Saves everything in an import plan, all at once so a failure leaves the server as it was
+async fn save_import(
+    fancy_db: &FancyPool,
+    guild: &Guild,
+    plan: &ImportPlan,
+) -> Result<(), sqlx::Error> {
+    let guild_id = guild.id.0 as i64;
+    let mut transaction = fancy_db.pool().begin().await?;
+    sqlx::query!(
+        "INSERT INTO guild (id, name) VALUES ($1, $2) ON CONFLICT (id) DO UPDATE SET name = $2",
+        guild_id,
+        guild.name
+    )
+    .execute(&mut transaction)
+    .await?;
+    if let Some(prefixes) = &plan.prefixes {
+        sqlx::query!("DELETE FROM guild_prefixes WHERE guild_id = $1", guild_id)
+            .execute(&mut transaction)
+            .await?;
+        for prefix in prefixes {
+            sqlx::query!(
+                "INSERT INTO guild_prefixes (guild_id, prefix) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                guild_id,
+                prefix
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+    }
+    if let Some(channel_id) = plan.log_channel_id {
+        sqlx::query!(
+            "UPDATE guild SET log_channel_id = $2 WHERE id = $1",
+            guild_id,
+            channel_id.0 as i64
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+    for binding in &plan.reaction_roles {
+        sqlx::query!("INSERT INTO reaction_roles (guild_id, role_id, channel_id, message_id, emoji, emoji_id, name, exclusive_group, mode, description, required_role_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT (guild_id, message_id, emoji, role_id) DO UPDATE SET channel_id = $3, name = $7, exclusive_group = $8, mode = $9, description = $10, required_role_id = $11",
+            guild_id,
+            binding.role.id as i64,
+            binding.channel_id as i64,
+            binding.message_id as i64,
+            binding.emoji,
+            binding.emoji_id.map(|id| id as i64),
+            binding.name,
+            binding.exclusive_group,
+            binding.mode,
+            binding.description,
+            binding.required_role.as_ref().map(|role| role.id as i64)
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+    for rules in &plan.rules {
+        sqlx::query!(
+            "INSERT INTO reaction_role_rules (message_id, guild_id, channel_id, max_roles, dm_on_reject) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (message_id) DO UPDATE SET max_roles = $4, dm_on_reject = $5 WHERE reaction_role_rules.guild_id = EXCLUDED.guild_id",
+            rules.message_id as i64,
+            guild_id,
+            rules.channel_id as i64,
+            rules.max_roles,
+            rules.dm_on_reject
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+    for panel in &plan.panels {
+        sqlx::query!(
+            "INSERT INTO reaction_panels (message_id, guild_id, channel_id, title) VALUES ($1, $2, $3, $4) ON CONFLICT (message_id) DO UPDATE SET title = $4",
+            panel.message_id as i64,
+            guild_id,
+            panel.channel_id as i64,
+            panel.title
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// What importing a config will do, with every role and channel mapped to this server's
+struct ImportPlan {
+    /// Replaces every prefix the guild has
+    prefixes: Option<Vec<String>>,
+    log_channel_id: Option<ChannelId>,
+    reaction_roles: Vec<ReactionRoleConfig>,
+    rules: Vec<RulesConfig>,
+    panels: Vec<PanelConfig>,
+    /// A line for each thing that will change
+    changes: Vec<String>,
+    /// Where each exported message's reaction roles, rules and panel go, by the exported message's id
+    destinations: HashMap<u64, Destination>,
+    /// A line for each thing that couldn't be mapped
+    skipped: Vec<String>,
+    /// How many reaction roles are being left out
+    skipped_bindings: usize,
+}
+
+/// Where an exported message's reaction roles, rules and panel end up in this server
+#[derive(Clone, Copy)]
+enum Destination {
+    /// The message is still in this server, so everything stays on it
+    Existing(ChannelId),
+    /// The message is a panel that isn't in this server, so it's posted again in this channel
+    Repost(ChannelId),
+}
+
+impl Destination {
+    fn channel_id(self) -> ChannelId {
+        match self {
+            Destination::Existing(channel_id) | Destination::Repost(channel_id) => channel_id,
+        }
+    }
+
+    /// Describes the message for the list of changes
+    fn describe(self, message_id: u64) -> String {
+        match self {
+            Destination::Existing(_) => format!("message {}", message_id),
+            Destination::Repost(channel_id) => {
+                format!("the panel posted again in <#{}>", channel_id.0)
+            }
+        }
+    }
+}
+
+/// Finds a role in the guild by its id, or by its name if the id isn't there
+fn map_role(guild: &Guild, role: &NamedId) -> Option<RoleId> {
+    let role_id = RoleId(role.id);
+    if guild.roles.contains_key(&role_id) {
+        return Some(role_id);
+    }
+    guild
+        .roles
+        .values()
+        .find(|guild_role| !role.name.is_empty() && guild_role.name == role.name)
+        .map(|guild_role| guild_role.id)
+}
+
+/// Finds a channel in the guild by its id, or by its name if the id isn't there
+fn map_channel(guild: &Guild, channel: &NamedId) -> Option<ChannelId> {
+    let channel_id = ChannelId(channel.id);
+    if guild.channels.contains_key(&channel_id) {
+        return Some(channel_id);
+    }
+    guild
+        .channels
+        .values()
+        .find(|guild_channel| !channel.name.is_empty() && guild_channel.name == channel.name)
+        .map(|guild_channel| guild_channel.id)
+}
+
+/// Maps everything in `config` to this guild, and works out how it differs from the `current` config
+async fn plan_import(
+    ctx: &Context,
+    guild: &Guild,
+    config: GuildConfig,
+    current: &GuildConfig,
+) -> ImportPlan {
+    let mut plan = ImportPlan {
+        prefixes: None,
+        log_channel_id: None,
+        reaction_roles: Vec::new(),
+        rules: Vec::new(),
+        panels: Vec::new(),
+        changes: Vec::new(),
+        destinations: HashMap::new(),
+        skipped: Vec::new(),
+        skipped_bindings: 0,
+    };
+
+    let mut prefixes = Vec::new();
+    for prefix in config.prefixes {
+        let prefix = normalize_prefix(&prefix);
+        if prefixes.contains(&prefix) {
+            continue;
+        }
+        match validate_prefix(&prefix) {
+            Ok(()) if prefixes.len() < MAX_PREFIXES => prefixes.push(prefix),
+            Ok(()) => plan.skipped.push(format!(
+                "Prefix ``{}``: servers can only have {} prefixes",
+                prefix, MAX_PREFIXES
+            )),
+            Err(reason) => plan
+                .skipped
+                .push(format!("Prefix ``{}``: {}", prefix, reason)),
+        }
+    }
+    if !prefixes.is_empty() && prefixes != current.prefixes {
+        plan.changes.push(format!(
+            "Prefixes → {}",
+            prefixes
+                .iter()
+                .map(|prefix| format!("``{}``", prefix))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        plan.prefixes = Some(prefixes);
+    }
+    if let Some(wanted) = config.log_channel {
+        match map_channel(guild, &wanted) {
+            Some(channel_id) => {
+                if current.log_channel.as_ref().map(|channel| channel.id) != Some(channel_id.0) {
+                    plan.changes
+                        .push(format!("Log channel → <#{}>", channel_id.0));
+                    plan.log_channel_id = Some(channel_id);
+                }
+            }
+            None => plan
+                .skipped
+                .push(format!("Log channel: no channel called `#{}`", wanted.name)),
+        }
+    }
+
+    // Work out where each exported message's reaction roles, rules and panel end up in this server.
+    let mut locations: BTreeMap<u64, NamedId> = BTreeMap::new();
+    let exported_messages = config
+        .reaction_roles
+        .iter()
+        .map(|binding| {
+            (
+                binding.message_id,
+                binding.channel_id,
+                &binding.channel_name,
+            )
+        })
+        .chain(
+            config
+                .rules
+                .iter()
+                .map(|rules| (rules.message_id, rules.channel_id, &rules.channel_name)),
+        )
+        .chain(
+            config
+                .panels
+                .iter()
+                .map(|panel| (panel.message_id, panel.channel_id, &panel.channel_name)),
+        );
+    for (message_id, channel_id, channel_name) in exported_messages {
+        let location = locations.entry(message_id).or_insert_with(|| NamedId {
+            id: channel_id,
+            name: String::new(),
+        });
+        if location.name.is_empty() {
+            location.name = channel_name.clone();
+        }
+    }
+    let panel_messages: HashSet<u64> = config.panels.iter().map(|panel| panel.message_id).collect();
+    let mut binding_counts: HashMap<u64, usize> = HashMap::new();
+    for binding in &config.reaction_roles {
+        *binding_counts.entry(binding.message_id).or_default() += 1;
+    }
+    for (message_id, channel) in &locations {
+        let destination = match map_channel(guild, channel) {
+            None if channel.name.is_empty() => Err("its channel isn't in this server".to_owned()),
+            None => Err(format!("there's no channel called `#{}`", channel.name)),
+            Some(channel_id) => {
+                let still_here = channel_id.0 == channel.id
+                    && channel_id.message(&ctx.http, *message_id).await.is_ok();
+                if still_here {
+                    Ok(Destination::Existing(channel_id))
+                } else if panel_messages.contains(message_id) {
+                    Ok(Destination::Repost(channel_id))
+                } else {
+                    Err(format!(
+                        "it isn't in <#{}>, or I can't see it, and only panels can be posted again",
+                        channel_id.0
+                    ))
+                }
+            }
+        };
+        match destination {
+            Ok(destination) => {
+                plan.destinations.insert(*message_id, destination);
+            }
+            Err(reason) => {
+                let bindings = binding_counts.get(message_id).copied().unwrap_or(0);
+                plan.skipped_bindings += bindings;
+                plan.skipped.push(format!(
+                    "Message {}, with {} reaction role(s): {}",
+                    message_id, bindings, reason
+                ));
+            }
+        }
+    }
+
+    for mut binding in config.reaction_roles {
+        let destination = match plan.destinations.get(&binding.message_id) {
+            Some(destination) => *destination,
+            None => continue,
+        };
+        let on_message = destination.describe(binding.message_id);
+        let emoji = emoji_display(&binding.name, binding.emoji_id.map(|id| id as i64));
+        if let Err(reason) = binding.mode.parse::<ReactionMode>() {
+            plan.skipped
+                .push(format!("{} on {}: {}", emoji, on_message, reason));
+            plan.skipped_bindings += 1;
+            continue;
+        }
+        let role_id = match map_role(guild, &binding.role) {
+            Some(role_id) => role_id,
+            None => {
+                plan.skipped.push(format!(
+                    "{} on {}: no role called `{}`",
+                    emoji, on_message, binding.role.name
+                ));
+                plan.skipped_bindings += 1;
+                continue;
+            }
+        };
+        let required_role_id = match &binding.required_role {
+            Some(required_role) => match map_role(guild, required_role) {
+                Some(role_id) => Some(role_id),
+                None => {
+                    plan.skipped.push(format!(
+                        "{} on {}: no role called `{}` to require",
+                        emoji, on_message, required_role.name
+                    ));
+                    plan.skipped_bindings += 1;
+                    continue;
+                }
+            },
+            None => None,
+        };
+        binding.role.id = role_id.0;
+        if let (Some(required_role), Some(required_role_id)) =
+            (binding.required_role.as_mut(), required_role_id)
+        {
+            required_role.id = required_role_id.0;
+        }
+        binding.channel_id = destination.channel_id().0;
+
+        // The same checks as adding a reaction role by hand, or every reaction would fail once it's made
+        if let Err(reason) =
+            check_reaction_role(ctx, guild.id, destination.channel_id(), role_id).await
+        {
+            plan.skipped
+                .push(format!("{} on {}: {}", emoji, on_message, reason));
+            plan.skipped_bindings += 1;
+            continue;
+        }
+        if let Some(emoji_id) = binding.emoji_id {
+            let emoji_identifier = EmojiIdentifier {
+                id: EmojiId(emoji_id),
+                name: binding.name.clone(),
+            };
+            if let Err(reason) = check_custom_emoji(ctx, &emoji_identifier).await {
+                plan.skipped
+                    .push(format!("{} on {}: {}", emoji, on_message, reason));
+                plan.skipped_bindings += 1;
+                continue;
+            }
+        }
+
+        let existing = match destination {
+            Destination::Existing(_) => current.reaction_roles.iter().find(|existing| {
+                existing.message_id == binding.message_id
+                    && existing.emoji == binding.emoji
+                    && existing.role.id == binding.role.id
+            }),
+            Destination::Repost(_) => None,
+        };
+        let role_name = guild
+            .roles
+            .get(&role_id)
+            .map_or_else(|| role_id.0.to_string(), |role| role.name.clone());
+        match existing {
+            None => plan
+                .changes
+                .push(format!("Add {} → `{}` on {}", emoji, role_name, on_message)),
+            Some(existing)
+                if existing.exclusive_group != binding.exclusive_group
+                    || existing.mode != binding.mode
+                    || existing.description != binding.description
+                    || existing.required_role.as_ref().map(|role| role.id)
+                        != binding.required_role.as_ref().map(|role| role.id) =>
+            {
+                plan.changes.push(format!(
+                    "Change {} → `{}` on {}",
+                    emoji, role_name, on_message
+                ))
+            }
+            Some(_) => continue,
+        }
+        plan.reaction_roles.push(binding);
+    }
+
+    for mut rules in config.rules {
+        let destination = match plan.destinations.get(&rules.message_id) {
+            Some(destination) => *destination,
+            None => continue,
+        };
+        rules.channel_id = destination.channel_id().0;
+        if let Destination::Existing(_) = destination {
+            let existing = current
+                .rules
+                .iter()
+                .find(|existing| existing.message_id == rules.message_id);
+            if existing.map_or(false, |existing| {
+                existing.max_roles == rules.max_roles && existing.dm_on_reject == rules.dm_on_reject
+            }) {
+                continue;
+            }
+        }
+        plan.changes.push(format!(
+            "Rules on {}: role limit {}, DM on reject {}",
+            destination.describe(rules.message_id),
+            rules
+                .max_roles
+                .map_or_else(|| "none".to_owned(), |max| max.to_string()),
+            if rules.dm_on_reject { "yes" } else { "no" }
+        ));
+        plan.rules.push(rules);
+    }
+
+    for mut panel in config.panels {
+        let destination = match plan.destinations.get(&panel.message_id) {
+            Some(destination) => *destination,
+            None => continue,
+        };
+        panel.channel_id = destination.channel_id().0;
+        match destination {
+            Destination::Existing(_) => {
+                let existing = current
+                    .panels
+                    .iter()
+                    .find(|existing| existing.message_id == panel.message_id);
+                if existing.map_or(false, |existing| existing.title == panel.title) {
+                    continue;
+                }
+                plan.changes.push(format!(
+                    "Panel `{}` on message {}",
+                    panel.title, panel.message_id
+                ));
+            }
+            Destination::Repost(channel_id) => plan.changes.push(format!(
+                "Post the panel `{}` again in <#{}>, since its message isn't in this server",
+                panel.title, channel_id.0
+            )),
+        }
+        plan.panels.push(panel);
+    }
+
+    plan
+}
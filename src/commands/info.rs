@@ -1,4 +1,7 @@
-use crate::core::built_info;
+use crate::core::{
+    built_info,
+    structs::SettingsContainer,
+};
 use crate::ShardManagerContainer;
 use chrono::Duration;
 #[allow(unused_imports)]
@@ -13,12 +16,10 @@ use serenity::{
     framework::standard::{
         macros::command,
         Args,
-        CommandError,
         CommandResult,
     },
     model::{
         id::GuildId,
-        id::UserId,
         prelude::Message,
     },
     prelude::Context,
@@ -27,40 +28,66 @@ use serenity::{
 #[command]
 #[aliases("version", "v")]
 /// Tells some information about the bot
-fn about(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let bot_owner = UserId(77_812_253_511_913_472).to_user(&ctx)?;
-    let _ = msg.channel_id.say(
-        &ctx.http,
-        format!(
-            "<@{}> version {}, is developed by {} with help from serenity and its resources.\nSource code can be found at https://github.com/Arzte/Arzte-bot",
-            ctx.cache.read().user.id,
-            built_info::PKG_VERSION,
-            bot_owner.name
-        ),
-    );
+async fn about(ctx: &Context, msg: &Message) -> CommandResult {
+    let settings_lock = {
+        let data = ctx.data.read().await;
+        match data.get::<SettingsContainer>() {
+            Some(v) => std::sync::Arc::clone(v),
+            None => {
+                let _ = msg
+                    .reply(&ctx, "There was a problem getting the settings")
+                    .await;
+
+                return Ok(());
+            }
+        }
+    };
+    let maintainer = settings_lock.read().await.maintainer.clone();
+    // Without one set, whoever owns the bot's application is its maintainer
+    let maintainer = match maintainer {
+        Some(maintainer) => maintainer,
+        None => {
+            let info = ctx.http.get_current_application_info().await?;
+            match info.team {
+                Some(team) => team.name,
+                None => info.owner.name,
+            }
+        }
+    };
+    let _ = msg
+        .channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "<@{}> version {}, is developed by {} with help from serenity and its resources.\nSource code can be found at https://github.com/Arzte/Arzte-bot",
+                ctx.cache.current_user_id().await,
+                built_info::PKG_VERSION,
+                maintainer
+            ),
+        )
+        .await;
     Ok(())
 }
 
 #[command]
 /// Shows the avatar for the user or specified user.
-fn avatar(context: &mut Context, msg: &Message, args: Args) -> CommandResult {
+async fn avatar(context: &Context, msg: &Message, args: Args) -> CommandResult {
     let face = if msg.mentions.is_empty() {
         if args.is_empty() {
             msg.author.face()
         } else {
-            let result: Result<String, Box<dyn std::error::Error>> = try {
-                msg.guild_id
+            let result: Result<String, Box<dyn std::error::Error + Send + Sync>> = async {
+                let guild = msg
+                    .guild_id
                     .ok_or("Failed to get GuildId from Message")?
                     .to_guild_cached(&context)
-                    .ok_or("Failed to get Guild from GuildId")?
-                    .read()
-                    .members_starting_with(args.rest(), false, true)
-                    .first()
-                    .ok_or("Could not find member")?
-                    .user_id()
-                    .to_user(&context)?
-                    .face()
-            };
+                    .await
+                    .ok_or("Failed to get Guild from GuildId")?;
+                let members = guild.members_starting_with(args.rest(), false, true).await;
+                let (member, _) = members.first().ok_or("Could not find member")?;
+                Ok(member.user.face())
+            }
+            .await;
             match result {
                 Ok(face) => face,
                 Err(e) => {
@@ -74,40 +101,44 @@ fn avatar(context: &mut Context, msg: &Message, args: Args) -> CommandResult {
     };
     msg.channel_id
         .send_message(&context, |m| m.embed(|e| e.image(face)))
-        .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(()))
+        .await?;
+
+    Ok(())
 }
 
 #[command]
 #[only_in("guilds")]
 #[aliases("u")]
 /// Shows various information about a user
-fn user(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+async fn user(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     let guild_id = msg.guild_id.ok_or("Failed to get GuildID from Message.")?;
     // TODO: Find a user via userid if provided
     let member = if msg.mentions.is_empty() {
         if args.is_empty() {
-            msg.member(&ctx).ok_or("Could not find member.")?
+            msg.member(&ctx)
+                .await
+                .map_err(|_| "Could not find member.")?
         } else {
-            (*(guild_id
+            let guild = guild_id
                 .to_guild_cached(&ctx)
-                .ok_or("Failed to get Guild from GuildId")?
-                .read()
-                .members_starting_with(args.rest(), false, true)
-                .first()
-                .ok_or("Could not find member")?))
-            .clone()
+                .await
+                .ok_or("Failed to get Guild from GuildId")?;
+            let members = guild.members_starting_with(args.rest(), false, true).await;
+            let (member, _) = members.first().ok_or("Could not find member")?;
+            (*member).clone()
         }
     } else {
-        guild_id.member(
-            &ctx,
-            msg.mentions
-                .first()
-                .ok_or("Failed to get user mentioned.")?,
-        )?
+        guild_id
+            .member(
+                &ctx,
+                msg.mentions
+                    .first()
+                    .ok_or("Failed to get user mentioned.")?,
+            )
+            .await?
     };
 
-    let user = member.user.read();
-    let roles = member.roles(&ctx).map_or(
+    let roles = member.roles(&ctx).await.map_or(
         "No role data found for this user in the cache".to_owned(),
         |m| {
             let mut role_id_list = String::new();
@@ -117,6 +148,7 @@ fn user(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
             role_id_list
         },
     );
+    let user = member.user;
     let nickname = member.nick.map_or("None".to_owned(), |nick| nick);
     let member_joined = member.joined_at.map_or("Unavailable".to_owned(), |d| {
         d.format("%a, %d %h %Y @ %H:%M:%S").to_string()
@@ -145,30 +177,33 @@ fn user(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
                 e
             })
         })
-        .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(()))
+        .await?;
+
+    Ok(())
 }
 
 #[command]
 #[only_in("guilds")]
 #[aliases("g", "s", "guild")]
 /// Shows various information about a guild.
-fn server(ctx: &mut Context, msg: &Message, mut args: Args) -> CommandResult {
+async fn server(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let guild_id = if !args.is_empty() {
         GuildId(args.single::<u64>()?)
     } else if let Some(gid) = msg.guild_id {
         gid
     } else {
-        msg.channel_id.say(
-            &ctx.http,
-            "I was unable to get the current guild id, try again later.",
-        )?;
+        msg.channel_id
+            .say(
+                &ctx.http,
+                "I was unable to get the current guild id, try again later.",
+            )
+            .await?;
         return Ok(());
     };
     let guild = guild_id
         .to_guild_cached(&ctx)
-        .ok_or("No server with this Guild ID can be found")?
-        .read()
-        .clone();
+        .await
+        .ok_or("No server with this Guild ID can be found")?;
     let roles: String = {
         let role_hash = &guild.roles;
         let mut role_id_list = String::new();
@@ -247,15 +282,17 @@ fn server(ctx: &mut Context, msg: &Message, mut args: Args) -> CommandResult {
                 e
             })
         })
-        .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(()))
+        .await?;
+
+    Ok(())
 }
 
 #[command]
 #[aliases("p")]
 /// Does a quick test to find out the latancy of Discord Relative to the bot
-fn ping(ctx: &mut Context, msg: &Message) -> CommandResult {
+async fn ping(ctx: &Context, msg: &Message) -> CommandResult {
     let start = chrono::offset::Utc::now().timestamp_millis();
-    let mut message = msg.channel_id.say(&ctx.http, "Pong!")?;
+    let mut message = msg.channel_id.say(&ctx.http, "Pong!").await?;
     let message_latency = {
         let millis = message.timestamp.timestamp_millis() - start;
         if millis > 0 {
@@ -267,42 +304,26 @@ fn ping(ctx: &mut Context, msg: &Message) -> CommandResult {
 
     let latency = {
         let shard_manager = {
-            let data = match ctx.data.try_read() {
-                Some(v) => v,
-                None => {
-                    error!("Error getting data lock, trying again...");
-                    match ctx.data.try_read() {
-                        Some(v) => v,
-                        None => {
-                            error!("Can't get data lock");
-
-                            return Ok(());
-                        }
-                    }
-                }
-            };
+            let data = ctx.data.read().await;
             match data.get::<ShardManagerContainer>() {
                 Some(v) => std::sync::Arc::clone(v),
                 None => {
-                    let _ = msg.reply(&ctx, "There was a problem getting the shard manager");
+                    let _ = msg
+                        .reply(&ctx, "There was a problem getting the shard manager")
+                        .await;
 
                     return Ok(());
                 }
             }
         };
 
-        let manager = shard_manager
-            .try_lock()
-            .ok_or("Couldn't get a lock on the shard manager")?;
-        let runners = manager
-            .runners
-            .try_lock()
-            .ok_or("Couldn't get a lock on the current shard runner")?;
+        let manager = shard_manager.lock().await;
+        let runners = manager.runners.lock().await;
 
         let shard = match runners.get(&ShardId(ctx.shard_id)) {
             Some(runner) => runner,
             None => {
-                let _ = msg.reply(&ctx, "No shard found");
+                let _ = msg.reply(&ctx, "No shard found").await;
 
                 return Ok(());
             }
@@ -321,7 +342,7 @@ fn ping(ctx: &mut Context, msg: &Message) -> CommandResult {
         "Pong! \n**```prolog\nMessage Latency: {}ms, \n  Shard Latency: {}\n```**",
         message_latency, latency
     );
-    message.edit(&ctx, |m| m.content(string))?;
+    message.edit(&ctx, |m| m.content(string)).await?;
 
     Ok(())
 }
@@ -3,7 +3,6 @@ use serenity::{
     framework::standard::{
         macros::command,
         Args,
-        CommandError,
         CommandResult,
     },
     model::prelude::Message,
@@ -18,44 +17,33 @@ use serenity::{
 #[min_args(1)]
 /// For fun with math, does not currently support variables*
 /// *(There are plans to change that in the future!)
-fn math(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+async fn math(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     let mut namespace = fasteval::EmptyNamespace;
     let value = fasteval::ez_eval(&args.rest(), &mut namespace);
 
-    match value {
-        Ok(value) => msg
-            .channel_id
-            .say(&ctx.http, value)
-            .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(())),
-
+    let content = match value {
+        Ok(value) => value.to_string(),
         Err(err) => match err {
             fastevalError::Undefined(variable) => {
-                let content = content_safe(&ctx, variable, &ContentSafeOptions::default());
-                msg.channel_id
-                    .say(
-                        &ctx.http,
-                        format!(
-                            "Unknown variable: `{}`\nPS: Variables are unsupported",
-                            content
-                        ),
-                    )
-                    .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(()))
-            }
-            _ => {
-                let content =
-                    content_safe(&ctx, format!("{:#?}", err), &ContentSafeOptions::default());
-                msg.channel_id
-                    .say(&ctx.http, content)
-                    .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(()))
+                let content = content_safe(&ctx, variable, &ContentSafeOptions::default()).await;
+                format!(
+                    "Unknown variable: `{}`\nPS: Variables are unsupported",
+                    content
+                )
             }
+            _ => content_safe(&ctx, format!("{:#?}", err), &ContentSafeOptions::default()).await,
         },
-    }
+    };
+
+    msg.channel_id.say(&ctx.http, content).await?;
+
+    Ok(())
 }
 
 #[command]
 #[min_args(1)]
 /// For when math isn't precise enough for you. (15 second timeout on calculations)
-fn precision_math(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+async fn precision_math(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     let args_full = args.rest();
 
     let value = if args_full.contains("for") {
@@ -67,7 +55,9 @@ fn precision_math(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult
     } else if args_full.contains("while") {
         String::from("Illegal Character ``while``")
     } else {
-        match bc::bc_timeout!(args_full) {
+        // bc can take up to 15 seconds, so run it where it won't hold up everything else
+        let expression = args_full.to_owned();
+        match tokio::task::spawn_blocking(move || bc::bc_timeout!(&expression)).await? {
             Ok(value) => {
                 if value.len() < 2000 {
                     value
@@ -79,9 +69,9 @@ fn precision_math(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult
         }
     };
 
-    let content = content_safe(&ctx, value, &ContentSafeOptions::default());
+    let content = content_safe(&ctx, value, &ContentSafeOptions::default()).await;
+
+    msg.channel_id.say(&ctx.http, content).await?;
 
-    msg.channel_id
-        .say(&ctx.http, content)
-        .map_or_else(|e| Err(CommandError(e.to_string())), |_| Ok(()))
+    Ok(())
 }
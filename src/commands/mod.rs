@@ -1,4 +1,6 @@
 pub mod admin;
+pub mod config;
 pub mod info;
 pub mod math;
 pub mod owner;
+pub mod panel;
@@ -1,4 +1,13 @@
-use crate::ShardManagerContainer;
+use crate::{
+    core::{
+        prefixes::prefix_cache_stats,
+        settings::{
+            reload_settings,
+            settings_file,
+        },
+    },
+    ShardManagerContainer,
+};
 use chrono::Duration;
 #[allow(unused_imports)]
 use log::{
@@ -33,7 +42,7 @@ use std::{
 // Repeats what the user passed as argument but ensures that user and role
 // mentions are replaced with a safe textual alternative.
 // In this example channel mentions are excluded via the `ContentSafeOptions`.
-fn say(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+async fn say(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     let settings = if msg.guild_id.is_some() {
         // By default roles, users, and channel mentions are cleaned.
         ContentSafeOptions::default()
@@ -52,9 +61,9 @@ fn say(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
             .clean_user(false)
     };
 
-    let content = content_safe(&ctx.cache, &args.rest(), &settings);
+    let content = content_safe(&ctx.cache, &args.rest(), &settings).await;
 
-    if let Err(why) = msg.channel_id.say(&ctx.http, &content) {
+    if let Err(why) = msg.channel_id.say(&ctx.http, &content).await {
         println!("Error sending message: {:?}", why);
     }
 
@@ -64,21 +73,15 @@ fn say(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
 #[command]
 #[aliases("q")]
 /// Kills this instance of the bot, only available to bot owners
-fn quit(ctx: &mut Context, msg: &Message) -> CommandResult {
-    msg.reply(&ctx, "Shutting down!")?;
+async fn quit(ctx: &Context, msg: &Message) -> CommandResult {
+    msg.reply(&ctx, "Shutting down!").await?;
 
     if let Some(client) = Hub::current().client() {
         client.close(Some(Duration::seconds(2).to_std()?));
     }
     {
         let shard_manager = {
-            let data = match ctx.data.try_read() {
-                Some(v) => v,
-                None => {
-                    error!("Couldn't get data lock for a graceful shutdown, killing bot");
-                    std::process::exit(0)
-                }
-            };
+            let data = ctx.data.read().await;
 
             match data.get::<ShardManagerContainer>() {
                 Some(v) => std::sync::Arc::clone(v),
@@ -91,15 +94,8 @@ fn quit(ctx: &mut Context, msg: &Message) -> CommandResult {
             }
         };
 
-        if let Some(mut manager) = shard_manager.try_lock() {
-            info!("Telling serenity to close all shards, then shutdown");
-            manager.shutdown_all();
-        } else {
-            error!(
-                "Couldn't get the shard manager lock for a graceful shutdown, killing the bot..."
-            );
-            std::process::exit(0)
-        };
+        info!("Telling serenity to close all shards, then shutdown");
+        shard_manager.lock().await.shutdown_all().await;
     }
 
     Ok(())
@@ -114,8 +110,8 @@ use crate::core::{
 #[command]
 #[aliases("up")]
 /// Downloads the latest version of the bot if available, only available to bot owners
-fn update(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let reqwest = reqwest::blocking::ClientBuilder::new()
+async fn update(ctx: &Context, msg: &Message) -> CommandResult {
+    let reqwest = reqwest::ClientBuilder::new()
         .user_agent(format!(
             "{}/{}",
             built_info::PKG_NAME,
@@ -124,58 +120,71 @@ fn update(ctx: &mut Context, msg: &Message) -> CommandResult {
         .build()?;
     let github_latest_release: GithubRelease = reqwest
         .get("https://api.github.com/repos/Arzte/Arzte-bot/releases/latest")
-        .send()?
-        .json()?;
+        .send()
+        .await?
+        .json()
+        .await?;
     let github_tags: GithubTag = reqwest
         .get("https://api.github.com/repos/Arzte/Arzte-bot/tags")
-        .send()?
-        .json()?;
+        .send()
+        .await?
+        .json()
+        .await?;
     let github_latest_release_tag = github_latest_release.tag_name.as_str();
     let bot_verison = semver::Version::parse(built_info::PKG_VERSION)?;
     let github_latest_release_version = semver::Version::parse(github_latest_release_tag)?;
     let github_latest_tag_verison = semver::Version::parse(github_tags[0].name.as_ref())?;
 
     if bot_verison == github_latest_tag_verison {
-        if let Ok(msg_latest) = msg.channel_id.say(&ctx.http, "Already at latest version!") {
-            std::thread::sleep(std::time::Duration::from_secs(10));
+        if let Ok(msg_latest) = msg
+            .channel_id
+            .say(&ctx.http, "Already at latest version!")
+            .await
+        {
+            tokio::time::delay_for(std::time::Duration::from_secs(10)).await;
             // If the message can't be deleted, don't delete at all
-            if !msg.delete(&ctx).is_err() {
-                msg_latest.delete(&ctx)?;
+            if !msg.delete(&ctx).await.is_err() {
+                msg_latest.delete(&ctx).await?;
             }
         }
         return Ok(());
     } else if github_latest_release.assets.is_empty()
         || github_latest_tag_verison > github_latest_release_version
     {
-        if let Ok(msg_latest) = msg.channel_id.say(&ctx.http, "There's a release, however Travis hasn't successfully built the new version yet, perhaps try again in a few minutes?") {
-                std::thread::sleep(std::time::Duration::from_secs(10));
+        if let Ok(msg_latest) = msg.channel_id.say(&ctx.http, "There's a release, however Travis hasn't successfully built the new version yet, perhaps try again in a few minutes?").await {
+                tokio::time::delay_for(std::time::Duration::from_secs(10)).await;
                 // If the message can't be deleted, don't delete at all
-                if !msg.delete(&ctx).is_err() {
-                    msg_latest.delete(&ctx)?;
+                if !msg.delete(&ctx).await.is_err() {
+                    msg_latest.delete(&ctx).await?;
                 }
             }
         return Ok(());
     }
 
-    let mut message = msg.channel_id.say(
-        &ctx.http,
-        "Now downloading a new version of Arzte's Cute Bot, please wait....",
-    )?;
+    let mut message = msg
+        .channel_id
+        .say(
+            &ctx.http,
+            "Now downloading a new version of Arzte's Cute Bot, please wait....",
+        )
+        .await?;
 
     trace!("Downloading the latest release from github...");
 
     let download_file = "arzte.tar.gz";
-    let mut response = reqwest
+    let response = reqwest
         .get(&github_latest_release.assets[0].browser_download_url)
-        .send()?;
-    let mut download = File::create(download_file)?;
+        .send()
+        .await?;
     let dest = Path::new(download_file);
 
-    response.copy_to(&mut download)?;
+    fs::write(dest, response.bytes().await?)?;
 
-    message.edit(&ctx, |m| {
-        m.content("Download complete, extracting new version from downloaded archive.....")
-    })?;
+    message
+        .edit(&ctx, |m| {
+            m.content("Download complete, extracting new version from downloaded archive.....")
+        })
+        .await?;
     trace!("Extracting from downloaded archive");
     let tar_gz = File::open(dest)?;
     let tar = flate2::read::GzDecoder::new(tar_gz);
@@ -191,16 +200,20 @@ fn update(ctx: &mut Context, msg: &Message) -> CommandResult {
     fs::remove_file(bin_hash_path)?;
 
     if &hash != bin_hash_str {
-        message.edit(&ctx, |m| {
-            m.content("Hash check failed, can't update Arzte's Cute Bot")
-        })?;
+        message
+            .edit(&ctx, |m| {
+                m.content("Hash check failed, can't update Arzte's Cute Bot")
+            })
+            .await?;
         fs::remove_file(bin_path)?;
         return Ok(());
     }
 
-    message.edit(&ctx, |m| {
-        m.content("Download was successful, updating Arzte's Cute Bot....")
-    })?;
+    message
+        .edit(&ctx, |m| {
+            m.content("Download was successful, updating Arzte's Cute Bot....")
+        })
+        .await?;
     fs::rename(bin_path, "arzte")?;
     fs::metadata("arzte")?.permissions().set_mode(0o755);
 
@@ -212,18 +225,14 @@ fn update(ctx: &mut Context, msg: &Message) -> CommandResult {
         client.close(Some(Duration::seconds(2).to_std()?));
     }
 
-    message.edit(&ctx, |m| m.content("Updated! Restarting now!"))?;
+    message
+        .edit(&ctx, |m| m.content("Updated! Restarting now!"))
+        .await?;
 
     {
         let shard_manager = {
             trace!("Getting serenity's data lock...");
-            let data = match ctx.data.try_read() {
-                Some(data_lock) => data_lock,
-                None => {
-                    error!("Couldn't get data lock for a graceful shutdown, killing the bot...");
-                    std::process::exit(0);
-                }
-            };
+            let data = ctx.data.read().await;
             match data.get::<ShardManagerContainer>() {
                 Some(v) => std::sync::Arc::clone(v),
                 None => {
@@ -236,16 +245,62 @@ fn update(ctx: &mut Context, msg: &Message) -> CommandResult {
         };
 
         trace!("Getting a lock on shard_manager");
-        if let Some(mut manager) = shard_manager.try_lock() {
-            info!("Telling serenity to close all shards, then shutdown");
-            manager.shutdown_all();
-        } else {
-            error!(
-                "Couldn't get the shard manager lock for a graceful shutdown, killing the bot..."
-            );
-            std::process::exit(0);
-        };
+        let mut manager = shard_manager.lock().await;
+        info!("Telling serenity to close all shards, then shutdown");
+        manager.shutdown_all().await;
     }
 
     Ok(())
 }
+
+#[command]
+/// Shows how prefix lookups have gone since the bot started, only available to bot owners
+async fn prefix_stats(ctx: &Context, msg: &Message) -> CommandResult {
+    let stats = prefix_cache_stats(ctx).await;
+    let lookups = stats.hits + stats.misses;
+    let hit_rate = if lookups == 0 {
+        0.0
+    } else {
+        stats.hits as f64 / lookups as f64 * 100.0
+    };
+
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Prefix cache: {} hit(s), {} miss(es) ({:.1}% hit rate), {} failure(s), {}/{} server(s) cached",
+                stats.hits, stats.misses, hit_rate, stats.failures, stats.cached_guilds, stats.capacity
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+/// Reads the settings file again and starts using the new settings, without reconnecting,
+/// only available to bot owners
+async fn reload(ctx: &Context, msg: &Message) -> CommandResult {
+    let path = settings_file(ctx).await?.path;
+    let reply = match reload_settings(ctx).await {
+        Ok(needs_restart) if needs_restart.is_empty() => {
+            format!("Reloaded the settings from {}", path.display())
+        }
+        Ok(needs_restart) => format!(
+            "Reloaded the settings from {}, but changes to {} only take effect after a restart",
+            path.display(),
+            needs_restart
+                .iter()
+                .map(|setting| format!("`{}`", setting))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Err(e) => format!(
+            "Kept the old settings, the new ones couldn't be used. {}",
+            e
+        ),
+    };
+    msg.channel_id.say(&ctx.http, reply).await?;
+
+    Ok(())
+}
@@ -0,0 +1,313 @@
+use crate::{
+    commands::admin::{
+        parse_message_url,
+        say_chunked,
+    },
+    core::{
+        panels::{
+            refresh_panel,
+            Panel,
+        },
+        reaction_cache::refresh_reaction_messages,
+        structs::PoolContainer,
+        utils::message_url,
+    },
+};
+use log::warn;
+use serenity::{
+    framework::standard::{
+        macros::command,
+        Args,
+        CommandResult,
+    },
+    model::prelude::{
+        ChannelId,
+        Message,
+    },
+    prelude::Context,
+};
+use std::sync::Arc;
+
+/// Discord won't accept embed titles longer than this
+const MAX_TITLE_LENGTH: usize = 256;
+
+#[command]
+#[min_args(1)]
+#[required_permissions(ADMINISTRATOR)]
+/// Posts a new reaction role panel in this channel, with the given title.
+/// Roles are added to it with reaction_add and the panel's link,
+/// and the panel keeps its list of roles up to date on its own.
+///
+/// Example usage:
+/// panel create <title>
+/// panel create Pick your colour
+///
+/// Restricted to Users with the Administrator permission
+async fn create(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
+    let title = args.rest().trim().to_owned();
+    if title.chars().count() > MAX_TITLE_LENGTH {
+        return Err(format!(
+            "Panel titles can be at most {} characters long",
+            MAX_TITLE_LENGTH
+        )
+        .into());
+    }
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
+    };
+
+    let mut panel = Panel {
+        channel_id: msg.channel_id,
+        message_id: msg.id,
+        title,
+        entries: Vec::new(),
+    };
+    panel.message_id = msg
+        .channel_id
+        .send_message(&ctx.http, |m| m.embed(|e| panel.embed(e)))
+        .await?
+        .id;
+
+    let inserted = sqlx::query!(
+        "INSERT INTO reaction_panels (message_id, guild_id, channel_id, title) VALUES ($1, $2, $3, $4)",
+        panel.message_id.0 as i64,
+        guild_id.0 as i64,
+        panel.channel_id.0 as i64,
+        panel.title
+    )
+    .execute(fancy_db.pool())
+    .await;
+
+    // A panel that isn't in the database would never be updated, so don't leave it lying around.
+    if let Err(e) = inserted {
+        let _ = panel
+            .channel_id
+            .delete_message(&ctx.http, panel.message_id)
+            .await;
+        return Err(e.into());
+    }
+    refresh_reaction_messages(ctx, &[panel.message_id]).await;
+
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Created the panel, add roles to it with ``reaction_add <emoji> <role_id> {}``",
+                message_url(guild_id.0, panel.channel_id.0, panel.message_id.0)
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[min_args(2)]
+#[required_permissions(ADMINISTRATOR)]
+/// Changes the title of a panel.
+///
+/// Example usage:
+/// panel edit <panel_url> <title>
+///
+/// Restricted to Users with the Administrator permission
+async fn edit(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
+    let (_, message_id) = parse_message_url(&args.single::<String>()?)?;
+    let title = args.rest().trim().to_owned();
+    if title.chars().count() > MAX_TITLE_LENGTH {
+        return Err(format!(
+            "Panel titles can be at most {} characters long",
+            MAX_TITLE_LENGTH
+        )
+        .into());
+    }
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
+    };
+
+    let updated = sqlx::query!(
+        "UPDATE reaction_panels SET title = $1 WHERE guild_id = $2 AND message_id = $3",
+        title,
+        guild_id.0 as i64,
+        message_id.0 as i64
+    )
+    .execute(fancy_db.pool())
+    .await?;
+
+    if updated == 0 {
+        return Err("That message isn't a panel".into());
+    }
+
+    refresh_panel(ctx, guild_id, message_id).await?;
+
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!("Changed the panel's title to ``{}``", title),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[min_args(1)]
+#[required_permissions(ADMINISTRATOR)]
+/// Posts a panel again, in this channel or the channel given,
+/// moving its reaction roles and rules over and deleting the old panel.
+///
+/// Example usage:
+/// panel repost <panel_url> [channel]
+/// panel repost https://discordapp.com/channels/197169999494774784/355889026726887426/703682563398697000 #roles
+///
+/// Restricted to Users with the Administrator permission
+async fn repost(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
+    let (_, old_message_id) = parse_message_url(&args.single::<String>()?)?;
+    let channel_id = if args.is_empty() {
+        msg.channel_id
+    } else {
+        args.single::<ChannelId>()?
+    };
+    let in_guild = channel_id
+        .to_channel_cached(&ctx)
+        .await
+        .and_then(|channel| channel.guild())
+        .map_or(false, |channel| channel.guild_id == guild_id);
+    if !in_guild {
+        return Err("That channel isn't in this server".into());
+    }
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
+    };
+
+    // Only panels from this server can be reposted, or an admin could move another server's panel here
+    let mut panel = Panel::fetch(&fancy_db, guild_id, old_message_id)
+        .await?
+        .ok_or("There's no panel in this server with that message")?;
+    let old_channel_id = panel.channel_id;
+
+    let new_message = channel_id
+        .send_message(&ctx.http, |m| m.embed(|e| panel.embed(e)))
+        .await?;
+    panel.channel_id = channel_id;
+    panel.message_id = new_message.id;
+
+    let moved: Result<(), sqlx::Error> = async {
+        let mut transaction = fancy_db.pool().begin().await?;
+        sqlx::query!(
+            "UPDATE reaction_panels SET message_id = $1, channel_id = $2 WHERE guild_id = $3 AND message_id = $4",
+            panel.message_id.0 as i64,
+            panel.channel_id.0 as i64,
+            guild_id.0 as i64,
+            old_message_id.0 as i64
+        )
+        .execute(&mut transaction)
+        .await?;
+        // Reaction roles made exclusive to the old message need to be exclusive to the new one instead
+        sqlx::query!(
+            "UPDATE reaction_roles SET message_id = $1, channel_id = $2, exclusive_group = CASE WHEN exclusive_group = $5 THEN $6 ELSE exclusive_group END WHERE guild_id = $3 AND message_id = $4",
+            panel.message_id.0 as i64,
+            panel.channel_id.0 as i64,
+            guild_id.0 as i64,
+            old_message_id.0 as i64,
+            old_message_id.0.to_string(),
+            panel.message_id.0.to_string()
+        )
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query!(
+            "UPDATE reaction_role_rules SET message_id = $1, channel_id = $2 WHERE guild_id = $3 AND message_id = $4",
+            panel.message_id.0 as i64,
+            panel.channel_id.0 as i64,
+            guild_id.0 as i64,
+            old_message_id.0 as i64
+        )
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await
+    }
+    .await;
+
+    // The new panel would never be updated if its reaction roles weren't moved over, so don't leave it lying around.
+    if let Err(e) = moved {
+        let _ = channel_id.delete_message(&ctx.http, panel.message_id).await;
+        return Err(e.into());
+    }
+    refresh_reaction_messages(ctx, &[old_message_id, panel.message_id]).await;
+
+    // Reactions are shown in the order they're added, so add them in the same order as the panel lists them.
+    for entry in &panel.entries {
+        ctx.http
+            .create_reaction(channel_id.0, panel.message_id.0, &entry.reaction_type)
+            .await?;
+    }
+
+    if let Err(e) = old_channel_id
+        .delete_message(&ctx.http, old_message_id)
+        .await
+    {
+        warn!("Failed to delete old panel {}: {}", old_message_id, e);
+    }
+
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Reposted the panel:\n{}",
+                message_url(guild_id.0, panel.channel_id.0, panel.message_id.0)
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[required_permissions(ADMINISTRATOR)]
+/// Lists every panel in this server.
+///
+/// Restricted to Users with the Administrator permission
+async fn list(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Couldn't get guild id")?;
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(data.get::<PoolContainer>().ok_or("Couldn't get fancy db")?)
+    };
+
+    let panels = sqlx::query!(
+        "SELECT message_id, channel_id, title FROM reaction_panels WHERE guild_id = $1 ORDER BY title",
+        guild_id.0 as i64
+    )
+    .fetch_all(fancy_db.pool())
+    .await?;
+
+    if panels.is_empty() {
+        msg.channel_id
+            .say(
+                &ctx.http,
+                "There aren't any panels in this server yet, make one with ``panel create <title>``",
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let mut response = String::from("**Panels in this server:**\n");
+    for panel in panels {
+        response.push_str(&format!(
+            "{}: {}\n",
+            panel.title,
+            message_url(guild_id.0, panel.channel_id as u64, panel.message_id as u64)
+        ));
+    }
+
+    say_chunked(ctx, msg.channel_id, &response).await
+}
@@ -0,0 +1,189 @@
+use crate::{
+    core::{
+        error::EventError,
+        utils::message_url,
+    },
+    PoolContainer,
+};
+use lazy_static::lazy_static;
+use log::warn;
+use serenity::{
+    model::prelude::{
+        ChannelId,
+        GuildId,
+        Reaction,
+        RoleId,
+        UserId,
+    },
+    prelude::Context,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// How many role changes a guild can have logged in each window, before the rest are skipped
+const AUDIT_LIMIT: u32 = 20;
+const AUDIT_WINDOW: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// When each guild's current window started, how many changes were logged in it,
+    /// and how many have been skipped since the last one that was logged
+    static ref AUDIT_WINDOWS: Mutex<HashMap<GuildId, (Instant, u32, u32)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// What happened to a role a member reacted for
+pub enum AuditOutcome {
+    Added,
+    Removed,
+    /// The reaction broke the message's rules, with why
+    Rejected(String),
+    /// The role couldn't be changed, with why
+    Failed(String),
+}
+
+/// A role a member picked up or dropped through a reaction
+pub struct AuditEntry {
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+    pub role_id: RoleId,
+    /// The reaction, formatted so discord renders it
+    pub emoji: Option<String>,
+    pub channel_id: Option<ChannelId>,
+    pub message_id: Option<u64>,
+    pub outcome: AuditOutcome,
+}
+
+impl AuditEntry {
+    /// Makes an entry for a role change caused by a reaction, if it was in a guild
+    pub fn from_reaction(
+        reaction: &Reaction,
+        role_id: RoleId,
+        outcome: AuditOutcome,
+    ) -> Option<Self> {
+        Some(AuditEntry {
+            guild_id: reaction.guild_id?,
+            user_id: reaction.user_id,
+            role_id,
+            emoji: Some(reaction.emoji.to_string()),
+            channel_id: Some(reaction.channel_id),
+            message_id: Some(reaction.message_id.0),
+            outcome,
+        })
+    }
+}
+
+/// Posts an entry to the log channel with [`audit_log`], only warning if it can't be
+pub async fn try_audit_log(ctx: &Context, entry: AuditEntry) {
+    if let Err(e) = audit_log(ctx, entry).await {
+        warn!("Failed to post to the log channel: {}", e);
+    }
+}
+
+/// Posts an embed about a reaction role change to the guild's log channel,
+/// doing nothing if the guild hasn't set one.
+///
+/// Each guild only gets so many of these a minute, so mass reactions don't flood the channel,
+/// the next one that is posted says how many were skipped.
+pub async fn audit_log(ctx: &Context, entry: AuditEntry) -> Result<(), EventError> {
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(EventError::ShareMapGetError)?,
+        )
+    };
+
+    let log_channel_id = sqlx::query!(
+        "SELECT log_channel_id FROM guild WHERE id = $1",
+        entry.guild_id.0 as i64
+    )
+    .fetch_optional(fancy_db.pool())
+    .await?
+    .and_then(|guild| guild.log_channel_id);
+    let log_channel_id = match log_channel_id {
+        Some(channel_id) => ChannelId(channel_id as u64),
+        None => return Ok(()),
+    };
+
+    let skipped = match take_slot(entry.guild_id) {
+        Some(skipped) => skipped,
+        None => return Ok(()),
+    };
+
+    let (title, colour, reason) = match entry.outcome {
+        AuditOutcome::Added => ("Role added", 0x2ecc71, None),
+        AuditOutcome::Removed => ("Role removed", 0x3498db, None),
+        AuditOutcome::Rejected(reason) => ("Reaction rejected", 0xe67e22, Some(reason)),
+        AuditOutcome::Failed(reason) => ("Role change failed", 0xe74c3c, Some(reason)),
+    };
+    let message = match (entry.channel_id, entry.message_id) {
+        (Some(channel_id), Some(message_id)) => {
+            Some(message_url(entry.guild_id.0, channel_id.0, message_id))
+        }
+        _ => None,
+    };
+
+    log_channel_id
+        .send_message(&ctx.http, |m| {
+            m.embed(|e| {
+                e.title(title);
+                e.colour(colour);
+                e.field("Member", format!("<@{}>", entry.user_id.0), true);
+                e.field("Role", format!("<@&{}>", entry.role_id.0), true);
+                if let Some(emoji) = entry.emoji {
+                    e.field("Emoji", emoji, true);
+                }
+                if let Some(message) = message {
+                    e.field("Message", message, false);
+                }
+                if let Some(reason) = reason {
+                    e.field("Reason", reason, false);
+                }
+                if skipped > 0 {
+                    e.footer(|f| {
+                        f.text(format!(
+                            "{} earlier change(s) weren't logged to avoid flooding this channel",
+                            skipped
+                        ))
+                    });
+                }
+                e.timestamp(chrono::Utc::now().to_rfc3339());
+                e
+            })
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Takes one of the guild's audit log slots for the current window, returning how many
+/// changes were skipped before this one, or `None` if there aren't any slots left.
+fn take_slot(guild_id: GuildId) -> Option<u32> {
+    // A poisoned lock only means another thread panicked mid update, the counts are still usable
+    let mut windows = AUDIT_WINDOWS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let now = Instant::now();
+    let (started, logged, skipped) = windows.entry(guild_id).or_insert((now, 0, 0));
+
+    if now.duration_since(*started) >= AUDIT_WINDOW {
+        *started = now;
+        *logged = 0;
+    }
+    if *logged >= AUDIT_LIMIT {
+        *skipped += 1;
+        return None;
+    }
+
+    *logged += 1;
+    Some(std::mem::replace(skipped, 0))
+}
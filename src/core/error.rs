@@ -14,6 +14,64 @@ pub enum ReactionError {
     NoRows,
     #[error("Couldn't find a matching guild member for user id")]
     ErrorFindingGuildMember(#[from] serenity::Error),
-    #[error("Error adding role")]
-    ErrorAddingRole,
+}
+
+#[derive(Error, Debug)]
+pub enum PanelError {
+    #[error("Unable to get container from sharemap")]
+    ShareMapGetError,
+    #[error("Database Error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("Discord Error: {0}")]
+    DiscordError(#[from] serenity::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum EventError {
+    #[error("Unable to get container from sharemap")]
+    ShareMapGetError,
+    #[error("Database Error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("Discord Error: {0}")]
+    DiscordError(#[from] serenity::Error),
+    #[error("Prefix Error: {0}")]
+    PrefixError(#[from] PrefixError),
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Database Error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("Couldn't write the config as TOML: {0}")]
+    TomlWriteError(#[from] toml::ser::Error),
+    #[error("Couldn't read the config as TOML: {0}")]
+    TomlReadError(#[from] toml::de::Error),
+    #[error("Couldn't read or write the config as JSON: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("The config is from a newer version of the bot (version {0}), and can't be imported")]
+    UnsupportedVersion(u32),
+}
+
+#[derive(Error, Debug)]
+pub enum PrefixError {
+    #[error("Unable to get container from sharemap")]
+    ShareMapGetError,
+    #[error("Database Error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum SettingsError {
+    #[error("Unable to get container from sharemap")]
+    ShareMapGetError,
+    #[error("Couldn't read the settings: {0}")]
+    ReadError(#[from] config::ConfigError),
+    #[error("The settings aren't valid:\n{}", .0.join("\n"))]
+    Invalid(Vec<String>),
+    #[error("{0}")]
+    ArgumentError(String),
+    #[error("Couldn't read the token from {0}: {1}")]
+    TokenFileError(String, std::io::Error),
+    #[error("The task reading the settings failed: {0}")]
+    TaskError(#[from] tokio::task::JoinError),
 }
@@ -0,0 +1,139 @@
+use lazy_static::lazy_static;
+use log::warn;
+use serde::Serialize;
+use serenity::model::prelude::Message;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    panic,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+lazy_static! {
+    /// The file errors are written to, if the `error_file` setting is set
+    static ref ERROR_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+    /// Things like the bot's token, which are masked in anything reported
+    static ref SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+/// Masks a secret, like the bot's token, wherever it shows up in errors from now on
+pub fn hide_secret(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    SECRETS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(secret.to_owned());
+}
+
+/// Replaces every secret passed to [`hide_secret`] in some text
+pub fn redact(text: &str) -> String {
+    let secrets = SECRETS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    secrets.iter().fold(text.to_owned(), |text, secret| {
+        text.replace(secret.as_str(), "<masked>")
+    })
+}
+
+/// An error written to the error file, as one line of JSON
+#[derive(Serialize, Debug)]
+pub struct ErrorRecord {
+    /// When the error happened, as an RFC 3339 timestamp
+    pub timestamp: String,
+    /// What failed, `panic` or `command`
+    pub kind: &'static str,
+    pub message: String,
+    /// Where in the code a panic happened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guild_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<u64>,
+}
+
+impl ErrorRecord {
+    fn new(kind: &'static str, message: String) -> Self {
+        ErrorRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            kind,
+            message,
+            location: None,
+            command: None,
+            user_id: None,
+            guild_id: None,
+            channel_id: None,
+        }
+    }
+
+    /// Makes a record for a command that returned an error
+    pub fn from_command(msg: &Message, command_name: &str, error: String) -> Self {
+        ErrorRecord {
+            command: Some(command_name.to_owned()),
+            user_id: Some(msg.author.id.0),
+            guild_id: msg.guild_id.map(|guild_id| guild_id.0),
+            channel_id: Some(msg.channel_id.0),
+            ..ErrorRecord::new("command", error)
+        }
+    }
+}
+
+/// Starts writing errors to a file, alongside anything else they're reported to.
+/// Panics are written there from now on too.
+pub fn start_error_file(path: PathBuf) {
+    *ERROR_FILE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(path);
+
+    let previous_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(message) => (*message).to_owned(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => "Box<Any>".to_owned(),
+            },
+        };
+        report_error(ErrorRecord {
+            location: info.location().map(|location| location.to_string()),
+            ..ErrorRecord::new("panic", message)
+        });
+        previous_hook(info);
+    }));
+}
+
+/// Adds an error to the error file, doing nothing if there isn't one
+pub fn report_error(mut record: ErrorRecord) {
+    record.message = redact(&record.message);
+
+    // A poisoned lock only means another thread panicked mid write, the path is still usable
+    let error_file = ERROR_FILE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let path = match error_file.as_ref() {
+        Some(path) => path,
+        None => return,
+    };
+
+    let result = serde_json::to_string(&record)
+        .map_err(std::io::Error::from)
+        .and_then(|line| {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", line)
+        });
+    if let Err(e) = result {
+        warn!(
+            "Failed to write an error to {}: {}, the error was {:?}",
+            path.display(),
+            e,
+            record
+        );
+    }
+}
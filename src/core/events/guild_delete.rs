@@ -0,0 +1,90 @@
+use crate::{
+    core::{
+        error::EventError,
+        prefixes::forget_prefixes,
+        reaction_cache::refresh_reaction_messages,
+        utils::is_missing_access,
+    },
+    PoolContainer,
+};
+use log::info;
+use serenity::{
+    model::prelude::{
+        GuildId,
+        MessageId,
+    },
+    prelude::Context,
+};
+use std::{
+    collections::HashSet,
+    sync::Arc,
+};
+
+/// Removes everything stored for a guild the bot has been removed from
+pub async fn guild_delete(ctx: &Context, guild_id: GuildId) -> Result<(), EventError> {
+    // Discord also sends a guild delete when a guild goes down during an outage,
+    // so make sure we've actually lost access to it before throwing its settings away.
+    match ctx.http.get_guild(guild_id.0).await {
+        Err(ref e) if is_missing_access(e) => {}
+        _ => return Ok(()),
+    }
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(EventError::ShareMapGetError)?,
+        )
+    };
+
+    let mut transaction = fancy_db.pool().begin().await?;
+    let removed = sqlx::query!(
+        "DELETE FROM reaction_roles WHERE guild_id = $1 RETURNING message_id",
+        guild_id.0 as i64
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM reaction_panels WHERE guild_id = $1",
+        guild_id.0 as i64
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM reaction_role_rules WHERE guild_id = $1",
+        guild_id.0 as i64
+    )
+    .execute(&mut transaction)
+    .await?;
+    // Queued role changes can't be made anymore, and would only be retried until they're marked as failed
+    sqlx::query!(
+        "DELETE FROM role_queue WHERE guild_id = $1",
+        guild_id.0 as i64
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM guild_prefixes WHERE guild_id = $1",
+        guild_id.0 as i64
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!("DELETE FROM guild WHERE id = $1", guild_id.0 as i64)
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    forget_prefixes(ctx, guild_id).await?;
+
+    let messages: HashSet<MessageId> = removed
+        .iter()
+        .map(|row| MessageId(row.message_id as u64))
+        .collect();
+    refresh_reaction_messages(ctx, &messages.into_iter().collect::<Vec<_>>()).await;
+
+    info!(
+        "Removed all data for guild {}, after being removed from it",
+        guild_id
+    );
+
+    Ok(())
+}
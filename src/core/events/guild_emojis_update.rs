@@ -0,0 +1,110 @@
+use crate::{
+    core::{
+        error::EventError,
+        guild_log::guild_log,
+        panels::refresh_panel,
+        reaction_cache::refresh_reaction_messages,
+    },
+    PoolContainer,
+};
+use log::warn;
+use serenity::{
+    model::prelude::{
+        Emoji,
+        EmojiId,
+        GuildId,
+        MessageId,
+    },
+    prelude::Context,
+};
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    sync::Arc,
+};
+
+/// Removes the reaction roles using custom emojis that have been deleted,
+/// and lets the guild's log channel know about them.
+pub async fn guild_emojis_update(
+    ctx: &Context,
+    guild_id: GuildId,
+    current_state: &HashMap<EmojiId, Emoji>,
+) -> Result<(), EventError> {
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(EventError::ShareMapGetError)?,
+        )
+    };
+
+    let custom_emojis = sqlx::query!(
+        "SELECT DISTINCT emoji, name, emoji_id FROM reaction_roles WHERE guild_id = $1 AND emoji_id IS NOT NULL",
+        guild_id.0 as i64
+    )
+    .fetch_all(fancy_db.pool())
+    .await?;
+
+    // Reaction roles can use emojis from any server the bot's in,
+    // so an emoji is only gone if no server has it anymore.
+    let mut known_emojis: HashSet<EmojiId> = current_state.keys().copied().collect();
+    for other_guild_id in ctx.cache.guilds().await {
+        if other_guild_id == guild_id {
+            continue;
+        }
+        if let Some(emojis) = ctx
+            .cache
+            .guild_field(other_guild_id, |guild| {
+                guild.emojis.keys().copied().collect::<Vec<_>>()
+            })
+            .await
+        {
+            known_emojis.extend(emojis);
+        }
+    }
+
+    let deleted: Vec<_> = custom_emojis
+        .into_iter()
+        .filter(|row| {
+            row.emoji_id
+                .map_or(false, |id| !known_emojis.contains(&EmojiId(id as u64)))
+        })
+        .collect();
+
+    if deleted.is_empty() {
+        return Ok(());
+    }
+
+    let mut report = String::new();
+    for emoji in deleted {
+        let removed = sqlx::query!(
+            "DELETE FROM reaction_roles WHERE guild_id = $1 AND emoji = $2 RETURNING message_id",
+            guild_id.0 as i64,
+            emoji.emoji
+        )
+        .fetch_all(fancy_db.pool())
+        .await?;
+
+        let messages: HashSet<MessageId> = removed
+            .iter()
+            .map(|row| MessageId(row.message_id as u64))
+            .collect();
+        refresh_reaction_messages(ctx, &messages.iter().copied().collect::<Vec<_>>()).await;
+        for message_id in &messages {
+            if let Err(e) = refresh_panel(ctx, guild_id, *message_id).await {
+                warn!("Failed to refresh panel {}: {}", message_id, e);
+            }
+        }
+
+        report.push_str(&format!(
+            "The emoji `:{}:` was deleted, removed {} reaction role(s) for it, from {} message(s).\n",
+            emoji.name,
+            removed.len(),
+            messages.len()
+        ));
+    }
+
+    guild_log(ctx, guild_id, &report).await
+}
@@ -0,0 +1,103 @@
+use crate::{
+    core::{
+        error::EventError,
+        guild_log::guild_log,
+        panels::refresh_panel,
+        reaction_cache::refresh_reaction_messages,
+    },
+    PoolContainer,
+};
+use log::warn;
+use serenity::{
+    model::prelude::{
+        GuildId,
+        MessageId,
+        Role,
+        RoleId,
+    },
+    prelude::Context,
+};
+use std::{
+    collections::HashSet,
+    sync::Arc,
+};
+
+/// Removes the reaction roles for a role that's been deleted, and lets the guild's
+/// log channel know about them, along with any reaction roles that needed the role.
+pub async fn guild_role_delete(
+    ctx: &Context,
+    guild_id: GuildId,
+    role_id: RoleId,
+    role: Option<&Role>,
+) -> Result<(), EventError> {
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(EventError::ShareMapGetError)?,
+        )
+    };
+
+    let mut transaction = fancy_db.pool().begin().await?;
+    let removed = sqlx::query!(
+        "DELETE FROM reaction_roles WHERE guild_id = $1 AND role_id = $2 RETURNING message_id",
+        guild_id.0 as i64,
+        role_id.0 as i64
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    // Queued changes to the role can't be made anymore, and would only be retried until they're marked as failed
+    sqlx::query!(
+        "DELETE FROM role_queue WHERE guild_id = $1 AND role_id = $2",
+        guild_id.0 as i64,
+        role_id.0 as i64
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    // Reaction roles that need the deleted role can't be picked up by anyone anymore, but
+    // quietly dropping the requirement could give out roles that were meant to be gated,
+    // so they're left for an admin to sort out.
+    let requiring = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM reaction_roles WHERE guild_id = $1 AND required_role_id = $2",
+        guild_id.0 as i64,
+        role_id.0 as i64
+    )
+    .fetch_one(fancy_db.pool())
+    .await?
+    .count
+    .unwrap_or(0);
+
+    if removed.is_empty() && requiring == 0 {
+        return Ok(());
+    }
+
+    let messages: HashSet<MessageId> = removed
+        .iter()
+        .map(|row| MessageId(row.message_id as u64))
+        .collect();
+    refresh_reaction_messages(ctx, &messages.iter().copied().collect::<Vec<_>>()).await;
+    for message_id in &messages {
+        if let Err(e) = refresh_panel(ctx, guild_id, *message_id).await {
+            warn!("Failed to refresh panel {}: {}", message_id, e);
+        }
+    }
+
+    let role_name = role.map_or_else(|| role_id.0.to_string(), |role| role.name.clone());
+    let mut report = format!("The role `{}` was deleted.", role_name);
+    if !removed.is_empty() {
+        report.push_str(&format!(
+            "\nRemoved {} reaction role(s) for it, from {} message(s).",
+            removed.len(),
+            messages.len()
+        ));
+    }
+    if requiring > 0 {
+        report.push_str(&format!(
+            "\n{} reaction role(s) need this role before they can be picked up, and can't be until they're changed with reaction_add.",
+            requiring
+        ));
+    }
+
+    guild_log(ctx, guild_id, &report).await
+}
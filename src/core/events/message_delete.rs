@@ -0,0 +1,61 @@
+use crate::{
+    core::{
+        error::EventError,
+        reaction_cache::{
+            has_reaction_roles,
+            refresh_reaction_messages,
+        },
+    },
+    PoolContainer,
+};
+use log::info;
+use serenity::{
+    model::prelude::MessageId,
+    prelude::Context,
+};
+use std::sync::Arc;
+
+/// Removes the reaction roles, panel and rules for a message that's been deleted
+pub async fn message_delete(ctx: &Context, message_id: MessageId) -> Result<(), EventError> {
+    // Most deleted messages never had anything stored for them, so they don't need a trip to the database
+    if !has_reaction_roles(ctx, message_id).await {
+        return Ok(());
+    }
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(EventError::ShareMapGetError)?,
+        )
+    };
+
+    let removed = sqlx::query!(
+        "DELETE FROM reaction_roles WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .execute(fancy_db.pool())
+    .await?;
+    sqlx::query!(
+        "DELETE FROM reaction_panels WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .execute(fancy_db.pool())
+    .await?;
+    sqlx::query!(
+        "DELETE FROM reaction_role_rules WHERE message_id = $1",
+        message_id.0 as i64
+    )
+    .execute(fancy_db.pool())
+    .await?;
+
+    refresh_reaction_messages(ctx, &[message_id]).await;
+    if removed > 0 {
+        info!(
+            "Removed {} reaction role(s) from deleted message {}",
+            removed, message_id
+        );
+    }
+
+    Ok(())
+}
@@ -1,2 +1,6 @@
+pub mod guild_delete;
+pub mod guild_emojis_update;
+pub mod guild_role_delete;
+pub mod message_delete;
 pub mod reaction_add;
 pub mod reaction_remove;
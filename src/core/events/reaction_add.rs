@@ -1,88 +1,406 @@
 use crate::{
-    core::error::ReactionError,
+    core::{
+        audit_log::{
+            try_audit_log,
+            AuditEntry,
+            AuditOutcome,
+        },
+        error::ReactionError,
+        reaction_cache::has_reaction_roles,
+        role_queue::queue_roles,
+        structs::{
+            ReactionMode,
+            RoleAction,
+        },
+        utils::{
+            emoji_key,
+            reaction_type_from_row,
+        },
+    },
     PoolContainer,
-    TokioContainer,
 };
+use lazy_static::lazy_static;
+use log::warn;
 use serenity::{
     model::prelude::{
         Reaction,
-        ReactionType,
+        RoleId,
     },
     prelude::Context,
 };
-use std::sync::Arc;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// How long a rejected reaction is remembered for, which only needs to cover its remove event arriving
+const REJECTION_MEMORY: Duration = Duration::from_secs(60);
 
-/// Adds a preset role to a user based on a reaction add event in a guild,
+lazy_static! {
+    /// Reactions the bot took back off for breaking the rules, by message, user and emoji,
+    /// along with when, so their remove events don't take away roles the member already had
+    static ref REJECTED_REACTIONS: Mutex<HashMap<(u64, u64, String), Instant>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Queues every preset role to be added to a user based on a reaction add event in a guild,
 /// that matches the reaction in the DB, provided the it's added in the same
-/// guild
-pub fn reaction_add(ctx: &Context, reaction_add: &Reaction) -> Result<(), ReactionError> {
+/// guild.
+/// What happens depends on the reaction role's mode, see [`ReactionMode`].
+/// If the reaction is part of an exclusive group, the user's other roles
+/// from that group are taken away, along with their reactions for them.
+///
+/// Reactions that break the message's rules, like a missing prerequisite role,
+/// or going over the message's role limit, are removed instead.
+///
+/// The bot's own reactions are ignored.
+///
+/// [`ReactionMode`]: crate::core::structs::ReactionMode
+pub async fn reaction_add(ctx: &Context, reaction_add: &Reaction) -> Result<(), ReactionError> {
     let guild_id = *reaction_add
         .guild_id
         .ok_or(ReactionError::NoGuildId)?
         .as_u64() as i64;
+    // The bot reacts to its own panels and reaction roles so there's something to click,
+    // which mustn't be treated like a member picking a role.
+    if reaction_add.user_id == ctx.cache.current_user_id().await {
+        return Ok(());
+    }
+    // Most reactions are on ordinary messages, which don't need a trip to the database
+    if !has_reaction_roles(ctx, reaction_add.message_id).await {
+        return Err(ReactionError::NoRows);
+    }
     let message_id = *reaction_add.message_id.as_u64() as i64;
+    let emoji = match emoji_key(&reaction_add.emoji) {
+        Some(emoji) => emoji,
+        None => return Ok(()), // We don't know reaction type this is, so we ignore it.
+    };
+
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(ReactionError::ShareMapGetError)?,
+        )
+    };
+
+    let message_bindings = sqlx::query!(
+        "SELECT role_id, emoji, exclusive_group, mode, required_role_id FROM reaction_roles WHERE guild_id = $1 AND message_id = $2",
+        guild_id,
+        message_id
+    )
+    .fetch_all(fancy_db.pool())
+    .await?;
+    if !message_bindings.iter().any(|row| row.emoji == emoji) {
+        return Err(ReactionError::NoRows);
+    }
+    let rules = sqlx::query!(
+        "SELECT max_roles, dm_on_reject FROM reaction_role_rules WHERE message_id = $1 AND guild_id = $2",
+        message_id,
+        guild_id
+    )
+    .fetch_optional(fancy_db.pool())
+    .await?;
+
+    // Split the message's reaction roles into the ones for this reaction, and every other one,
+    // which are needed to check the message's role limit.
+    let (bindings, other_bindings): (Vec<Binding>, Vec<Binding>) = message_bindings
+        .into_iter()
+        .map(|row| Binding {
+            role_id: RoleId(row.role_id as u64),
+            exclusive_group: row.exclusive_group,
+            mode: row.mode.parse::<ReactionMode>().unwrap_or_default(),
+            required_role_id: row.required_role_id.map(|id| RoleId(id as u64)),
+            emoji: row.emoji,
+        })
+        .partition(|binding| binding.emoji == emoji);
 
-    let role_id = {
-        let (fancy_db, runtime_lock) = {
-            let data = ctx.data.try_read().ok_or(ReactionError::LockError)?;
-            let fancy_db = Arc::clone(
-                data.get::<PoolContainer>()
-                    .ok_or(ReactionError::ShareMapGetError)?,
-            );
-            let runtime_lock = Arc::clone(
-                data.get::<TokioContainer>()
-                    .ok_or(ReactionError::ShareMapGetError)?,
-            );
-            (fancy_db, runtime_lock)
-        };
-
-        match reaction_add.emoji {
-            ReactionType::Custom { id, .. } => {
-                let data = {
-                    let mut runtime = runtime_lock
-                        .try_lock()
-                        .map_err(|_| ReactionError::LockError)?;
-                    runtime
-                    .block_on(
-                        sqlx::query!(
-                            "SELECT role_id FROM reaction_roles WHERE guild_id = $1 AND message_id = $2 AND emoji_id = $3",
-                            guild_id,
-                            message_id,
-                            *id.as_u64() as i64
-                        )
-                        .fetch_optional(fancy_db.pool()),
-                    )?
-                };
-                data.ok_or(ReactionError::NoRows)?.role_id
+    let role_ids: Vec<RoleId> = bindings
+        .iter()
+        .filter(|binding| binding.mode != ReactionMode::RemoveOnly)
+        .map(|binding| binding.role_id)
+        .collect();
+    let removed_role_ids: Vec<RoleId> = bindings
+        .iter()
+        .filter(|binding| binding.mode == ReactionMode::RemoveOnly)
+        .map(|binding| binding.role_id)
+        .collect();
+    let verify = bindings
+        .iter()
+        .any(|binding| binding.mode == ReactionMode::Verify);
+    let groups: HashSet<String> = bindings
+        .iter()
+        .filter(|binding| binding.mode != ReactionMode::RemoveOnly)
+        .filter_map(|binding| binding.exclusive_group.clone())
+        .collect();
+
+    let guild_member = {
+        let guild = reaction_add.guild_id.ok_or(ReactionError::NoGuildId)?;
+        guild.member(ctx, reaction_add.user_id).await?
+    };
+
+    let max_roles = rules.as_ref().and_then(|rules| rules.max_roles);
+    if let Some(reason) = rule_violation(
+        ctx,
+        &guild_member.roles,
+        &bindings,
+        &other_bindings,
+        &groups,
+        max_roles,
+    )
+    .await
+    {
+        let dm_on_reject = rules.map_or(false, |rules| rules.dm_on_reject);
+        reject_reaction(ctx, reaction_add, &reason, dm_on_reject).await;
+        for role_id in &role_ids {
+            let outcome = AuditOutcome::Rejected(reason.clone());
+            if let Some(entry) = AuditEntry::from_reaction(reaction_add, *role_id, outcome) {
+                try_audit_log(ctx, entry).await;
             }
-            ReactionType::Unicode(ref name) => {
-                let data = {
-                    let mut runtime = runtime_lock
-                        .try_lock()
-                        .map_err(|_| ReactionError::LockError)?;
-                    runtime
-                    .block_on(
-                        sqlx::query!(
-                            "SELECT role_id FROM reaction_roles WHERE guild_id = $1 AND message_id = $2 AND name = $3",
-                            guild_id,
-                            message_id,
-                            name
-                        )
-                        .fetch_optional(fancy_db.pool()),
-                    )?
-                };
-                data.ok_or(ReactionError::NoRows)?.role_id
+        }
+        return Ok(());
+    }
+
+    queue_roles(ctx, reaction_add, &role_ids, RoleAction::Add).await?;
+    queue_roles(ctx, reaction_add, &removed_role_ids, RoleAction::Remove).await?;
+
+    // Verify reactions are taken off again once the role's been given, so the message stays clean.
+    if verify {
+        if let Err(e) = ctx
+            .http
+            .delete_reaction(
+                reaction_add.channel_id.0,
+                reaction_add.message_id.0,
+                Some(reaction_add.user_id.0),
+                &reaction_add.emoji,
+            )
+            .await
+        {
+            warn!("Failed to remove verify reaction: {}", e);
+        }
+    }
+
+    for group in groups {
+        let others = sqlx::query!(
+            "SELECT role_id, channel_id, message_id, name, emoji_id FROM reaction_roles WHERE guild_id = $1 AND exclusive_group = $2 AND NOT (message_id = $3 AND emoji = $4)",
+            guild_id,
+            group,
+            message_id,
+            emoji
+        )
+        .fetch_all(fancy_db.pool())
+        .await?;
+
+        // Only touch the roles the member actually has, which also tells us which of
+        // the group's reactions they've (most likely) clicked before.
+        // Roles that the new reaction also gives are left alone, so removing the
+        // old reaction doesn't take away what was just added.
+        let others: Vec<_> = others
+            .into_iter()
+            .filter(|row| {
+                let role_id = RoleId(row.role_id as u64);
+                guild_member.roles.contains(&role_id) && !role_ids.contains(&role_id)
+            })
+            .collect();
+
+        if others.is_empty() {
+            continue;
+        }
+
+        let old_role_ids: Vec<RoleId> = others
+            .iter()
+            .map(|row| RoleId(row.role_id as u64))
+            .collect();
+        queue_roles(ctx, reaction_add, &old_role_ids, RoleAction::Remove).await?;
+
+        for row in others {
+            let channel_id = match row.channel_id {
+                Some(channel_id) => channel_id as u64,
+                None => continue, // Reaction roles from before we stored channels can't be cleaned up
+            };
+            if let Err(e) = ctx
+                .http
+                .delete_reaction(
+                    channel_id,
+                    row.message_id as u64,
+                    Some(reaction_add.user_id.0),
+                    &reaction_type_from_row(&row.name, row.emoji_id),
+                )
+                .await
+            {
+                warn!(
+                    "Failed to remove old reaction for exclusive group {}: {}",
+                    group, e
+                );
             }
-            _ => return Ok(()), // We don't know reaction type this is, so we ignore it.
         }
-    };
+    }
 
-    let mut guild_member = {
-        let guild = reaction_add.guild_id.ok_or(ReactionError::NoGuildId)?;
-        guild.member(ctx, reaction_add.user_id)?
+    Ok(())
+}
+
+/// A reaction role on the message that was reacted to
+#[derive(Clone)]
+pub struct Binding {
+    pub role_id: RoleId,
+    pub emoji: String,
+    pub exclusive_group: Option<String>,
+    pub mode: ReactionMode,
+    pub required_role_id: Option<RoleId>,
+}
+
+/// Checks a reaction against the prerequisite roles of its reaction roles, and the
+/// message's role limit, returning the reason the reaction isn't allowed if it breaks either.
+pub async fn rule_violation(
+    ctx: &Context,
+    member_roles: &[RoleId],
+    bindings: &[Binding],
+    other_bindings: &[Binding],
+    groups: &HashSet<String>,
+    max_roles: Option<i32>,
+) -> Option<String> {
+    for binding in bindings {
+        if binding.mode == ReactionMode::RemoveOnly {
+            continue;
+        }
+        if let Some(required_role_id) = binding.required_role_id {
+            if !member_roles.contains(&required_role_id) {
+                return Some(format!(
+                    "You need the `{}` role before you can get `{}`.",
+                    role_name(ctx, required_role_id).await,
+                    role_name(ctx, binding.role_id).await
+                ));
+            }
+        }
+    }
+
+    let max_roles = max_roles? as usize;
+    let new_roles: HashSet<RoleId> = bindings
+        .iter()
+        .filter(|binding| binding.mode != ReactionMode::RemoveOnly)
+        .map(|binding| binding.role_id)
+        .filter(|role_id| !member_roles.contains(role_id))
+        .collect();
+    if new_roles.is_empty() {
+        return None;
+    }
+
+    // Roles in the same exclusive group are about to be swapped out, so they don't count.
+    let held_roles: HashSet<RoleId> = other_bindings
+        .iter()
+        .filter(|binding| binding.mode != ReactionMode::RemoveOnly)
+        .filter(|binding| {
+            binding
+                .exclusive_group
+                .as_ref()
+                .map_or(true, |group| !groups.contains(group))
+        })
+        .map(|binding| binding.role_id)
+        .filter(|role_id| member_roles.contains(role_id) && !new_roles.contains(role_id))
+        .collect();
+
+    if held_roles.len() + new_roles.len() > max_roles {
+        return Some(format!(
+            "You can only have {} role(s) from that message, remove one of your other reactions first.",
+            max_roles
+        ));
+    }
+
+    None
+}
+
+/// Returns the name of a role, or its id if it isn't in the cache
+async fn role_name(ctx: &Context, role_id: RoleId) -> String {
+    role_id
+        .to_role_cached(ctx)
+        .await
+        .map_or_else(|| role_id.0.to_string(), |role| role.name)
+}
+
+/// Takes a reaction that broke the message's rules back off, letting the member know why if wanted
+async fn reject_reaction(ctx: &Context, reaction: &Reaction, reason: &str, dm_on_reject: bool) {
+    if let Some(key) = rejection_key(reaction) {
+        let mut rejected = REJECTED_REACTIONS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        rejected.retain(|_, rejected_at| rejected_at.elapsed() < REJECTION_MEMORY);
+        rejected.insert(key, Instant::now());
+    }
+
+    if let Err(e) = ctx
+        .http
+        .delete_reaction(
+            reaction.channel_id.0,
+            reaction.message_id.0,
+            Some(reaction.user_id.0),
+            &reaction.emoji,
+        )
+        .await
+    {
+        warn!("Failed to remove rejected reaction: {}", e);
+    }
+
+    if !dm_on_reject {
+        return;
+    }
+
+    let guild_name = match reaction.guild_id {
+        Some(guild_id) => guild_id.name(ctx).await,
+        None => None,
+    }
+    .unwrap_or_else(|| "a server".to_owned());
+    let result = match reaction.user_id.create_dm_channel(ctx).await {
+        Ok(channel) => channel
+            .say(
+                &ctx.http,
+                format!(
+                    "Your reaction in **{}** was removed: {}",
+                    guild_name, reason
+                ),
+            )
+            .await
+            .map(|_| ()),
+        Err(e) => Err(e),
     };
+    if let Err(e) = result {
+        warn!(
+            "Failed to DM {} about a rejected reaction: {}",
+            reaction.user_id, e
+        );
+    }
+}
 
-    guild_member
-        .add_role(ctx, role_id as u64)
-        .map_err(|_| ReactionError::ErrorAddingRole)
+/// The key a reaction is remembered under in `REJECTED_REACTIONS`
+fn rejection_key(reaction: &Reaction) -> Option<(u64, u64, String)> {
+    Some((
+        reaction.message_id.0,
+        reaction.user_id.0,
+        emoji_key(&reaction.emoji)?,
+    ))
+}
+
+/// Returns whether a removed reaction was taken off by the bot because it broke the message's rules,
+/// forgetting about it, as each rejected reaction is only removed once.
+pub fn was_rejected(reaction: &Reaction) -> bool {
+    let key = match rejection_key(reaction) {
+        Some(key) => key,
+        None => return false,
+    };
+    REJECTED_REACTIONS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .remove(&key)
+        .map_or(false, |rejected_at| {
+            rejected_at.elapsed() < REJECTION_MEMORY
+        })
 }
@@ -1,88 +1,88 @@
 use crate::{
-    core::error::ReactionError,
+    core::{
+        error::ReactionError,
+        events::reaction_add::was_rejected,
+        reaction_cache::has_reaction_roles,
+        role_queue::queue_roles,
+        structs::{
+            ReactionMode,
+            RoleAction,
+        },
+        utils::emoji_key,
+    },
     PoolContainer,
-    TokioContainer,
 };
 use serenity::{
     model::prelude::{
         Reaction,
-        ReactionType,
+        RoleId,
     },
     prelude::Context,
 };
 use std::sync::Arc;
 
-/// Adds a preset role to a user based on a reaction add event in a guild,
-/// that matches the reaction in the DB, provided the it's added in the same
-/// guild
-pub fn reaction_remove(ctx: &Context, removed_reaction: &Reaction) -> Result<(), ReactionError> {
+/// Queues every preset role to be removed from a user based on a reaction remove event in a guild,
+/// that matches the reaction in the DB, provided the it's removed in the same
+/// guild, and the reaction role is in the toggle mode.
+/// The bot's own reactions are ignored, along with ones it removed for breaking the message's rules.
+pub async fn reaction_remove(
+    ctx: &Context,
+    removed_reaction: &Reaction,
+) -> Result<(), ReactionError> {
     let guild_id = *removed_reaction
         .guild_id
         .ok_or(ReactionError::NoGuildId)?
         .as_u64() as i64;
+    // The bot's own reactions never gave it any roles, so there's nothing to take away
+    if removed_reaction.user_id == ctx.cache.current_user_id().await {
+        return Ok(());
+    }
+    // The member never got anything for a reaction that broke the rules, and taking it away
+    // again could take roles they had before, like ones given by hand.
+    if was_rejected(removed_reaction) {
+        return Ok(());
+    }
+    // Most reactions are on ordinary messages, which don't need a trip to the database
+    if !has_reaction_roles(ctx, removed_reaction.message_id).await {
+        return Err(ReactionError::NoRows);
+    }
     let message_id = *removed_reaction.message_id.as_u64() as i64;
+    let emoji = match emoji_key(&removed_reaction.emoji) {
+        Some(emoji) => emoji,
+        None => return Ok(()), // We don't know reaction type this is, so we ignore it.
+    };
 
-    let role_id = {
-        let (fancy_db, runtime_lock) = {
-            let data = ctx.data.try_read().ok_or(ReactionError::LockError)?;
-            let fancy_db = Arc::clone(
+    let role_ids: Vec<RoleId> = {
+        let fancy_db = {
+            let data = ctx.data.read().await;
+            Arc::clone(
                 data.get::<PoolContainer>()
                     .ok_or(ReactionError::ShareMapGetError)?,
-            );
-            let runtime_lock = Arc::clone(
-                data.get::<TokioContainer>()
-                    .ok_or(ReactionError::ShareMapGetError)?,
-            );
-            (fancy_db, runtime_lock)
+            )
         };
 
-        match removed_reaction.emoji {
-            ReactionType::Custom { id, .. } => {
-                let data = {
-                    let mut runtime = runtime_lock
-                        .try_lock()
-                        .map_err(|_| ReactionError::LockError)?;
-                    runtime
-                        .block_on(
-                            sqlx::query!(
-                                "SELECT role_id FROM reaction_roles WHERE guild_id = $1 AND message_id = $2 AND emoji_id = $3",
-                                guild_id,
-                                message_id,
-                                *id.as_u64() as i64
-                            )
-                            .fetch_optional(fancy_db.pool()),
-                        )?
-                };
-                data.ok_or(ReactionError::NoRows)?.role_id
-            }
-            ReactionType::Unicode(ref name) => {
-                let data = {
-                    let mut runtime = runtime_lock
-                        .try_lock()
-                        .map_err(|_| ReactionError::LockError)?;
-                    runtime
-                        .block_on(
-                            sqlx::query!(
-                                "SELECT role_id FROM reaction_roles WHERE guild_id = $1 AND message_id = $2 AND name = $3",
-                                guild_id,
-                                message_id,
-                                name
-                            )
-                            .fetch_optional(fancy_db.pool()),
-                        )?
-                };
-                data.ok_or(ReactionError::NoRows)?.role_id
-            }
-            _ => return Ok(()), // We don't know reaction type this is, so we ignore it.
+        let data = sqlx::query!(
+            "SELECT role_id, mode FROM reaction_roles WHERE guild_id = $1 AND message_id = $2 AND emoji = $3",
+            guild_id,
+            message_id,
+            emoji
+        )
+        .fetch_all(fancy_db.pool())
+        .await?;
+        if data.is_empty() {
+            return Err(ReactionError::NoRows);
         }
-    };
 
-    let mut guild_member = {
-        let guild = removed_reaction.guild_id.ok_or(ReactionError::NoGuildId)?;
-        guild.member(ctx, removed_reaction.user_id)?
+        // Only toggle reaction roles take the role away when the reaction is removed,
+        // every other mode either already did what it needed to when it was added,
+        // or only ever cares about the reaction being added.
+        data.iter()
+            .filter(|row| {
+                row.mode.parse::<ReactionMode>().unwrap_or_default() == ReactionMode::Toggle
+            })
+            .map(|row| RoleId(row.role_id as u64))
+            .collect()
     };
 
-    guild_member
-        .remove_role(ctx, role_id as u64)
-        .map_err(|_| ReactionError::ErrorAddingRole)
+    queue_roles(ctx, removed_reaction, &role_ids, RoleAction::Remove).await
 }
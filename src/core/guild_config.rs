@@ -0,0 +1,327 @@
+use crate::core::{
+    error::ConfigError,
+    prefixes::fetch_prefixes,
+    utils::FancyPool,
+};
+use serde::{
+    Deserialize,
+    Deserializer,
+    Serialize,
+};
+use serenity::model::prelude::{
+    ChannelId,
+    Guild,
+    RoleId,
+};
+
+/// The version of the config file format, bumped whenever it changes in a way older files can't be read with
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Everything the bot stores about a guild, in the form it's exported and imported in.
+///
+/// Fields holding tables have to come after plain values, or they can't be written as TOML.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GuildConfig {
+    pub version: u32,
+    /// An empty list means the default prefix.
+    /// Configs exported before servers could have several prefixes have a single `prefix` instead.
+    #[serde(default, alias = "prefix", deserialize_with = "deserialize_prefixes")]
+    pub prefixes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_channel: Option<NamedId>,
+    #[serde(default)]
+    pub reaction_roles: Vec<ReactionRoleConfig>,
+    #[serde(default)]
+    pub rules: Vec<RulesConfig>,
+    #[serde(default)]
+    pub panels: Vec<PanelConfig>,
+}
+
+/// Reads the prefixes as a list, or as the single prefix older configs have
+fn deserialize_prefixes<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Prefixes {
+        List(Vec<String>),
+        Single(String),
+    }
+
+    Ok(match Prefixes::deserialize(deserializer)? {
+        Prefixes::List(prefixes) => prefixes,
+        Prefixes::Single(prefix) => vec![prefix],
+    })
+}
+
+/// A role or channel, along with its name, so it can still be found in a server where its id is different
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NamedId {
+    pub id: u64,
+    pub name: String,
+}
+
+/// A row of reaction_roles
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReactionRoleConfig {
+    pub channel_id: u64,
+    /// So the message's channel can be found by name in another server
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub channel_name: String,
+    pub message_id: u64,
+    /// The emoji's id for custom emojis, or the emoji itself for unicode emojis
+    pub emoji: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emoji_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclusive_group: Option<String>,
+    pub mode: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub role: NamedId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required_role: Option<NamedId>,
+}
+
+/// A row of reaction_role_rules
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RulesConfig {
+    pub channel_id: u64,
+    /// So the message's channel can be found by name in another server
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub channel_name: String,
+    pub message_id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_roles: Option<i32>,
+    #[serde(default)]
+    pub dm_on_reject: bool,
+}
+
+/// A row of reaction_panels
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PanelConfig {
+    pub channel_id: u64,
+    /// So the message's channel can be found by name in another server
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub channel_name: String,
+    pub message_id: u64,
+    pub title: String,
+}
+
+/// The formats a config can be exported and imported in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// The file extension for this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            ConfigFormat::Toml => "toml",
+            ConfigFormat::Json => "json",
+        }
+    }
+
+    /// Works out a file's format from its name
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let extension = filename.rsplit('.').next()?;
+        extension.parse().ok()
+    }
+}
+
+impl std::str::FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "toml" => Ok(ConfigFormat::Toml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(format!(
+                "`{}` isn't a config format, valid formats are `toml` and `json`",
+                s
+            )),
+        }
+    }
+}
+
+impl GuildConfig {
+    /// Gathers everything the bot stores about a guild out of the database.
+    /// The guild is used to look up the names of the roles and channels it refers to.
+    pub async fn fetch(fancy_db: &FancyPool, guild: &Guild) -> Result<Self, ConfigError> {
+        let guild_id = guild.id.0 as i64;
+        let role_ref = |role_id: i64| NamedId {
+            id: role_id as u64,
+            name: guild
+                .roles
+                .get(&RoleId(role_id as u64))
+                .map(|role| role.name.clone())
+                .unwrap_or_default(),
+        };
+        let channel_name = |channel_id: i64| {
+            guild
+                .channels
+                .get(&ChannelId(channel_id as u64))
+                .map(|channel| channel.name.clone())
+                .unwrap_or_default()
+        };
+        let channel_ref = |channel_id: i64| NamedId {
+            id: channel_id as u64,
+            name: channel_name(channel_id),
+        };
+
+        let guild_row = sqlx::query!("SELECT log_channel_id FROM guild WHERE id = $1", guild_id)
+            .fetch_optional(fancy_db.pool())
+            .await?;
+
+        // Reaction roles from before channels were stored can't be placed anywhere, so they're left out.
+        let reaction_roles = sqlx::query!(
+            "SELECT channel_id, message_id, emoji, name, emoji_id, exclusive_group, mode, description, role_id, required_role_id FROM reaction_roles WHERE guild_id = $1 AND channel_id IS NOT NULL ORDER BY message_id, added_at, role_id",
+            guild_id
+        )
+        .fetch_all(fancy_db.pool())
+        .await?
+        .into_iter()
+        .filter_map(|row| {
+            let channel_id = row.channel_id?;
+            Some(ReactionRoleConfig {
+                channel_id: channel_id as u64,
+                channel_name: channel_name(channel_id),
+                message_id: row.message_id as u64,
+                emoji: row.emoji,
+                name: row.name,
+                emoji_id: row.emoji_id.map(|id| id as u64),
+                exclusive_group: row.exclusive_group,
+                mode: row.mode,
+                description: row.description,
+                role: role_ref(row.role_id),
+                required_role: row.required_role_id.map(role_ref),
+            })
+        })
+        .collect();
+
+        let rules = sqlx::query!(
+            "SELECT channel_id, message_id, max_roles, dm_on_reject FROM reaction_role_rules WHERE guild_id = $1 ORDER BY message_id",
+            guild_id
+        )
+        .fetch_all(fancy_db.pool())
+        .await?
+        .into_iter()
+        .map(|row| RulesConfig {
+            channel_id: row.channel_id as u64,
+            channel_name: channel_name(row.channel_id),
+            message_id: row.message_id as u64,
+            max_roles: row.max_roles,
+            dm_on_reject: row.dm_on_reject,
+        })
+        .collect();
+
+        let panels = sqlx::query!(
+            "SELECT channel_id, message_id, title FROM reaction_panels WHERE guild_id = $1 ORDER BY message_id",
+            guild_id
+        )
+        .fetch_all(fancy_db.pool())
+        .await?
+        .into_iter()
+        .map(|row| PanelConfig {
+            channel_id: row.channel_id as u64,
+            channel_name: channel_name(row.channel_id),
+            message_id: row.message_id as u64,
+            title: row.title,
+        })
+        .collect();
+
+        Ok(GuildConfig {
+            version: CONFIG_VERSION,
+            prefixes: fetch_prefixes(fancy_db, guild.id).await?,
+            log_channel: guild_row
+                .as_ref()
+                .and_then(|row| row.log_channel_id)
+                .map(channel_ref),
+            reaction_roles,
+            rules,
+            panels,
+        })
+    }
+
+    /// Writes the config out in the given format
+    pub fn serialize(&self, format: ConfigFormat) -> Result<String, ConfigError> {
+        Ok(match format {
+            ConfigFormat::Toml => toml::to_string_pretty(self)?,
+            ConfigFormat::Json => serde_json::to_string_pretty(self)?,
+        })
+    }
+
+    /// Reads a config in the given format, rejecting ones from a newer version of the bot
+    pub fn parse(text: &str, format: ConfigFormat) -> Result<Self, ConfigError> {
+        let config: GuildConfig = match format {
+            ConfigFormat::Toml => toml::from_str(text)?,
+            ConfigFormat::Json => serde_json::from_str(text)?,
+        };
+        if config.version > CONFIG_VERSION {
+            return Err(ConfigError::UnsupportedVersion(config.version));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_single_prefix_from_older_configs() {
+        let config = GuildConfig::parse("version = 1\nprefix = \"!\"\n", ConfigFormat::Toml)
+            .expect("older toml config should parse");
+        assert_eq!(config.prefixes, vec!["!".to_owned()]);
+
+        let config = GuildConfig::parse(r#"{"version": 1, "prefix": "?"}"#, ConfigFormat::Json)
+            .expect("older json config should parse");
+        assert_eq!(config.prefixes, vec!["?".to_owned()]);
+    }
+
+    #[test]
+    fn reads_a_list_of_prefixes() {
+        let config = GuildConfig::parse(
+            "version = 1\nprefixes = [\"!\", \"a.\"]\n",
+            ConfigFormat::Toml,
+        )
+        .expect("toml config should parse");
+        assert_eq!(config.prefixes, vec!["!".to_owned(), "a.".to_owned()]);
+    }
+
+    #[test]
+    fn missing_prefixes_mean_the_default_prefix() {
+        let config = GuildConfig::parse("version = 1\n", ConfigFormat::Toml)
+            .expect("toml config should parse");
+        assert!(config.prefixes.is_empty());
+        assert!(config.reaction_roles.is_empty());
+    }
+
+    #[test]
+    fn rejects_configs_from_newer_versions() {
+        let text = format!("version = {}\n", CONFIG_VERSION + 1);
+        match GuildConfig::parse(&text, ConfigFormat::Toml) {
+            Err(ConfigError::UnsupportedVersion(version)) => {
+                assert_eq!(version, CONFIG_VERSION + 1)
+            }
+            other => panic!("expected an unsupported version error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn works_out_the_format_from_the_filename() {
+        assert_eq!(
+            ConfigFormat::from_filename("server.TOML"),
+            Some(ConfigFormat::Toml)
+        );
+        assert_eq!(
+            ConfigFormat::from_filename("server.json"),
+            Some(ConfigFormat::Json)
+        );
+        assert_eq!(ConfigFormat::from_filename("server.yaml"), None);
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{
+    core::error::EventError,
+    PoolContainer,
+};
+use serenity::{
+    model::prelude::{
+        ChannelId,
+        GuildId,
+    },
+    prelude::Context,
+};
+use std::sync::Arc;
+
+/// Posts a message to a guild's log channel, doing nothing if the guild hasn't set one
+pub async fn guild_log(ctx: &Context, guild_id: GuildId, content: &str) -> Result<(), EventError> {
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(EventError::ShareMapGetError)?,
+        )
+    };
+
+    let log_channel_id = sqlx::query!(
+        "SELECT log_channel_id FROM guild WHERE id = $1",
+        guild_id.0 as i64
+    )
+    .fetch_optional(fancy_db.pool())
+    .await?
+    .and_then(|guild| guild.log_channel_id);
+
+    if let Some(log_channel_id) = log_channel_id {
+        ChannelId(log_channel_id as u64)
+            .say(&ctx.http, content)
+            .await?;
+    }
+
+    Ok(())
+}
@@ -1,5 +1,15 @@
+pub mod audit_log;
 pub mod built_info;
 pub mod error;
+pub mod error_report;
 pub mod events;
+pub mod guild_config;
+pub mod guild_log;
+pub mod panels;
+pub mod prefixes;
+pub mod reaction_cache;
+pub mod role_queue;
+pub mod settings;
 pub mod structs;
+pub mod sync;
 pub mod utils;
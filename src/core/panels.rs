@@ -0,0 +1,158 @@
+use crate::{
+    core::{
+        error::PanelError,
+        utils::{
+            emoji_display,
+            reaction_type_from_row,
+            FancyPool,
+        },
+    },
+    PoolContainer,
+};
+use serenity::{
+    builder::CreateEmbed,
+    model::prelude::{
+        ChannelId,
+        GuildId,
+        MessageId,
+        ReactionType,
+        RoleId,
+    },
+    prelude::Context,
+};
+use std::sync::Arc;
+
+/// A message the bot posted, that lists the reaction roles on it
+pub struct Panel {
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    pub title: String,
+    /// The panel's reaction roles, one entry per emoji, in the order they were added
+    pub entries: Vec<PanelEntry>,
+}
+
+/// An emoji on a panel, and every role it gives
+pub struct PanelEntry {
+    pub emoji: String,
+    pub reaction_type: ReactionType,
+    pub display: String,
+    pub role_ids: Vec<RoleId>,
+    pub description: Option<String>,
+}
+
+impl Panel {
+    /// Fetches a panel and its reaction roles, returning `None` if the message isn't a panel in the guild
+    pub async fn fetch(
+        fancy_db: &FancyPool,
+        guild_id: GuildId,
+        message_id: MessageId,
+    ) -> Result<Option<Panel>, PanelError> {
+        let panel = sqlx::query!(
+            "SELECT channel_id, title FROM reaction_panels WHERE message_id = $1 AND guild_id = $2",
+            message_id.0 as i64,
+            guild_id.0 as i64
+        )
+        .fetch_optional(fancy_db.pool())
+        .await?;
+        let panel = match panel {
+            Some(panel) => panel,
+            None => return Ok(None),
+        };
+
+        let bindings = sqlx::query!(
+            "SELECT role_id, emoji, name, emoji_id, description FROM reaction_roles WHERE message_id = $1 AND guild_id = $2 ORDER BY added_at, role_id",
+            message_id.0 as i64,
+            guild_id.0 as i64
+        )
+        .fetch_all(fancy_db.pool())
+        .await?;
+
+        let mut entries: Vec<PanelEntry> = Vec::new();
+        for binding in bindings {
+            let role_id = RoleId(binding.role_id as u64);
+            match entries
+                .iter_mut()
+                .find(|entry| entry.emoji == binding.emoji)
+            {
+                Some(entry) => {
+                    entry.role_ids.push(role_id);
+                    if entry.description.is_none() {
+                        entry.description = binding.description;
+                    }
+                }
+                None => entries.push(PanelEntry {
+                    reaction_type: reaction_type_from_row(&binding.name, binding.emoji_id),
+                    display: emoji_display(&binding.name, binding.emoji_id),
+                    emoji: binding.emoji,
+                    role_ids: vec![role_id],
+                    description: binding.description,
+                }),
+            }
+        }
+
+        Ok(Some(Panel {
+            channel_id: ChannelId(panel.channel_id as u64),
+            message_id,
+            title: panel.title,
+            entries,
+        }))
+    }
+
+    /// Fills in an embed with the panel's title and every emoji → role on it
+    pub fn embed<'a>(&self, e: &'a mut CreateEmbed) -> &'a mut CreateEmbed {
+        let description = if self.entries.is_empty() {
+            "There aren't any roles on this panel yet.".to_owned()
+        } else {
+            self.entries
+                .iter()
+                .map(|entry| {
+                    let roles = entry
+                        .role_ids
+                        .iter()
+                        .map(|role_id| format!("<@&{}>", role_id.0))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    match entry.description {
+                        Some(ref description) => {
+                            format!("{} → {} — {}", entry.display, roles, description)
+                        }
+                        None => format!("{} → {}", entry.display, roles),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        e.title(&self.title);
+        e.description(description);
+        e
+    }
+}
+
+/// Redraws a panel's embed from the reaction roles currently on it,
+/// doing nothing if the message isn't a panel in the guild.
+pub async fn refresh_panel(
+    ctx: &Context,
+    guild_id: GuildId,
+    message_id: MessageId,
+) -> Result<(), PanelError> {
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(PanelError::ShareMapGetError)?,
+        )
+    };
+
+    let panel = match Panel::fetch(&fancy_db, guild_id, message_id).await? {
+        Some(panel) => panel,
+        None => return Ok(()),
+    };
+
+    panel
+        .channel_id
+        .edit_message(&ctx.http, message_id, |m| m.embed(|e| panel.embed(e)))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,388 @@
+use crate::core::{
+    error::PrefixError,
+    structs::{
+        PoolContainer,
+        PrefixHashMapContainer,
+        SettingsContainer,
+    },
+    utils::FancyPool,
+};
+use serenity::{
+    model::prelude::GuildId,
+    prelude::Context,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+    },
+};
+
+/// How many prefixes a guild can have at once
+pub const MAX_PREFIXES: usize = 10;
+/// How many characters a prefix can be
+pub const MAX_PREFIX_LENGTH: usize = 20;
+
+// How prefix lookups have gone since the bot started, see `prefix_cache_stats`
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+static LOOKUP_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+/// How prefix lookups have gone since the bot started
+pub struct PrefixCacheStats {
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups that had to go to the database
+    pub misses: u64,
+    /// Lookups that couldn't get an answer at all
+    pub failures: u64,
+    /// How many guilds are in the cache right now
+    pub cached_guilds: usize,
+    /// How many guilds the cache can hold before it starts dropping the least recently used ones
+    pub capacity: usize,
+}
+
+/// Returns how prefix lookups have gone since the bot started
+pub async fn prefix_cache_stats(ctx: &Context) -> PrefixCacheStats {
+    let cache_lock = {
+        let data = ctx.data.read().await;
+        data.get::<PrefixHashMapContainer>().map(Arc::clone)
+    };
+    let (cached_guilds, capacity) = match cache_lock {
+        Some(cache_lock) => {
+            let cache = cache_lock.lock().await;
+            (cache.len(), cache.cap())
+        }
+        None => (0, 0),
+    };
+
+    PrefixCacheStats {
+        hits: CACHE_HITS.load(Ordering::Relaxed),
+        misses: CACHE_MISSES.load(Ordering::Relaxed),
+        failures: LOOKUP_FAILURES.load(Ordering::Relaxed),
+        cached_guilds,
+        capacity,
+    }
+}
+
+/// Returns the prefix used in guilds that haven't set any of their own,
+/// which comes from the `default_prefix` setting.
+pub async fn default_prefix(ctx: &Context) -> Result<String, PrefixError> {
+    let settings_lock = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<SettingsContainer>()
+                .ok_or(PrefixError::ShareMapGetError)?,
+        )
+    };
+    let default_prefix = settings_lock.read().await.default_prefix.clone();
+
+    Ok(default_prefix)
+}
+
+/// Describes the prefixes the bot answers to in a guild, or in direct messages if there's no guild
+pub async fn describe_prefixes(
+    ctx: &Context,
+    guild_id: Option<GuildId>,
+) -> Result<String, PrefixError> {
+    let prefixes = match guild_id {
+        Some(guild_id) => guild_prefixes(ctx, guild_id).await?,
+        None => Vec::new(),
+    };
+    let list = if prefixes.is_empty() {
+        format!("``{}`` (the default)", default_prefix(ctx).await?)
+    } else {
+        prefixes
+            .iter()
+            .map(|prefix| format!("``{}``", prefix))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    Ok(format!(
+        "My prefixes here are {}, and mentioning me (<@{}>) always works too",
+        list,
+        ctx.cache.current_user_id().await.0
+    ))
+}
+
+/// Returns a guild's prefixes, oldest first, from the cache if they're in it.
+/// An empty list means the guild uses the default prefix.
+///
+/// This waits for the cache and database rather than giving up when they're busy,
+/// and only errors when the prefixes really can't be found. Callers mustn't treat
+/// an error as the guild using the default prefix, since it might have its own.
+pub async fn guild_prefixes(ctx: &Context, guild_id: GuildId) -> Result<Vec<String>, PrefixError> {
+    let result = cached_or_fetched_prefixes(ctx, guild_id).await;
+    if result.is_err() {
+        LOOKUP_FAILURES.fetch_add(1, Ordering::Relaxed);
+    }
+    result
+}
+
+async fn cached_or_fetched_prefixes(
+    ctx: &Context,
+    guild_id: GuildId,
+) -> Result<Vec<String>, PrefixError> {
+    let cache_lock = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PrefixHashMapContainer>()
+                .ok_or(PrefixError::ShareMapGetError)?,
+        )
+    };
+
+    if let Some(prefixes) = cache_lock.lock().await.get(&guild_id.0) {
+        CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+        return Ok(prefixes.clone());
+    }
+
+    CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+    reload_prefixes(ctx, guild_id).await
+}
+
+/// Reads a guild's prefixes from the database, replacing whatever the cache had for it.
+/// Anything that changes a guild's prefixes needs to call this afterwards.
+pub async fn reload_prefixes(ctx: &Context, guild_id: GuildId) -> Result<Vec<String>, PrefixError> {
+    let (fancy_db, cache_lock) = {
+        let data = ctx.data.read().await;
+        let fancy_db = Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(PrefixError::ShareMapGetError)?,
+        );
+        let cache_lock = Arc::clone(
+            data.get::<PrefixHashMapContainer>()
+                .ok_or(PrefixError::ShareMapGetError)?,
+        );
+        (fancy_db, cache_lock)
+    };
+
+    let prefixes = fetch_prefixes(&fancy_db, guild_id).await?;
+    cache_lock.lock().await.put(guild_id.0, prefixes.clone());
+
+    Ok(prefixes)
+}
+
+/// Fills the cache with the prefixes of the given guilds, so the first command in each
+/// doesn't have to wait on the database. Returns how many guilds were cached.
+///
+/// Every guild's own prefixes are read in one query, the guilds without any are cached
+/// as using the default prefix.
+pub async fn warm_prefix_cache(ctx: &Context, guild_ids: &[GuildId]) -> Result<usize, PrefixError> {
+    let (fancy_db, cache_lock) = {
+        let data = ctx.data.read().await;
+        let fancy_db = Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(PrefixError::ShareMapGetError)?,
+        );
+        let cache_lock = Arc::clone(
+            data.get::<PrefixHashMapContainer>()
+                .ok_or(PrefixError::ShareMapGetError)?,
+        );
+        (fancy_db, cache_lock)
+    };
+
+    let rows = sqlx::query!(
+        "SELECT guild_id, prefix FROM guild_prefixes ORDER BY guild_id, added_at, prefix"
+    )
+    .fetch_all(fancy_db.pool())
+    .await?;
+    let mut all_prefixes: HashMap<u64, Vec<String>> = HashMap::new();
+    for row in rows {
+        all_prefixes
+            .entry(row.guild_id as u64)
+            .or_default()
+            .push(row.prefix);
+    }
+
+    let mut cache = cache_lock.lock().await;
+    // Anything past the cache's size would only push out guilds cached just before it
+    let warmed = guild_ids.len().min(cache.cap());
+    for guild_id in &guild_ids[..warmed] {
+        let prefixes = all_prefixes.remove(&guild_id.0).unwrap_or_default();
+        cache.put(guild_id.0, prefixes);
+    }
+
+    Ok(warmed)
+}
+
+/// Drops a guild's prefixes from the cache, for when the bot leaves it
+pub async fn forget_prefixes(ctx: &Context, guild_id: GuildId) -> Result<(), PrefixError> {
+    let cache_lock = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PrefixHashMapContainer>()
+                .ok_or(PrefixError::ShareMapGetError)?,
+        )
+    };
+    cache_lock.lock().await.pop(&guild_id.0);
+
+    Ok(())
+}
+
+/// Reads a guild's prefixes from the database, oldest first
+pub async fn fetch_prefixes(
+    fancy_db: &FancyPool,
+    guild_id: GuildId,
+) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT prefix FROM guild_prefixes WHERE guild_id = $1 ORDER BY added_at, prefix",
+        guild_id.0 as i64
+    )
+    .fetch_all(fancy_db.pool())
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.prefix).collect())
+}
+
+/// Picks which of a guild's prefixes a message was sent with, preferring the longest
+/// one that matches, so a prefix that starts with another one still works.
+///
+/// Commands are case insensitive, so prefixes are too.
+pub fn matching_prefix<'a>(prefixes: &'a [String], content: &str) -> Option<&'a String> {
+    let content = content.to_lowercase();
+    prefixes
+        .iter()
+        .filter(|prefix| content.starts_with(&prefix.to_lowercase()))
+        .max_by_key(|prefix| prefix.len())
+}
+
+/// Puts a prefix in the form it's stored in. Commands are case insensitive,
+/// so prefixes are kept in lowercase, or ones with capitals would never match.
+pub fn normalize_prefix(prefix: &str) -> String {
+    prefix.to_lowercase()
+}
+
+/// Checks that a prefix can be used at all, returning why it can't if it isn't allowed
+pub fn validate_prefix(prefix: &str) -> Result<(), String> {
+    if prefix.is_empty() {
+        return Err("Prefixes can't be empty".to_owned());
+    }
+    if prefix.chars().count() > MAX_PREFIX_LENGTH {
+        return Err(format!(
+            "Prefixes can be at most {} characters long",
+            MAX_PREFIX_LENGTH
+        ));
+    }
+    if prefix.trim() != prefix {
+        return Err(
+            "Prefixes can't start or end with a space, since discord trims them off messages"
+                .to_owned(),
+        );
+    }
+    if prefix.chars().any(char::is_control) {
+        return Err(
+            "Prefixes can't have new lines or other invisible characters in them".to_owned(),
+        );
+    }
+    if prefix.contains('`') {
+        return Err("Prefixes can't have backticks in them".to_owned());
+    }
+    // Mentions are turned into ids by discord, so what's typed never matches the prefix
+    if prefix.contains("@everyone")
+        || prefix.contains("@here")
+        || prefix.contains("<@")
+        || prefix.contains("<#")
+    {
+        return Err("Prefixes can't have mentions in them".to_owned());
+    }
+
+    Ok(())
+}
+
+/// Returns why a prefix that's allowed might still make the bot hard to use,
+/// so whoever is setting it can be asked to confirm it first.
+pub fn risky_prefix(prefix: &str) -> Option<&'static str> {
+    if prefix.chars().all(char::is_alphanumeric) {
+        return Some(
+            "it's only letters and numbers, so ordinary chat messages may run commands by accident",
+        );
+    }
+    if prefix.chars().any(|c| "*_~|>".contains(c)) {
+        return Some(
+            "discord may turn it into formatting, so typing it might not work as expected",
+        );
+    }
+    if !prefix.is_ascii() {
+        return Some("it has characters that can be hard to type on some keyboards");
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefixes(prefixes: &[&str]) -> Vec<String> {
+        prefixes.iter().map(|prefix| (*prefix).to_owned()).collect()
+    }
+
+    #[test]
+    fn matches_prefixes_case_insensitively() {
+        let prefixes = prefixes(&["a.", "bot!"]);
+        assert_eq!(matching_prefix(&prefixes, "A.help"), Some(&"a.".to_owned()));
+        assert_eq!(
+            matching_prefix(&prefixes, "BOT!ping"),
+            Some(&"bot!".to_owned())
+        );
+        assert_eq!(matching_prefix(&prefixes, "!ping"), None);
+    }
+
+    #[test]
+    fn prefers_the_longest_matching_prefix() {
+        let prefixes = prefixes(&["!", "!!"]);
+        assert_eq!(matching_prefix(&prefixes, "!!help"), Some(&"!!".to_owned()));
+        assert_eq!(matching_prefix(&prefixes, "!help"), Some(&"!".to_owned()));
+    }
+
+    #[test]
+    fn stores_prefixes_in_lowercase() {
+        assert_eq!(normalize_prefix("Bot!"), "bot!");
+        assert_eq!(normalize_prefix("ÄB."), "äb.");
+    }
+
+    #[test]
+    fn accepts_ordinary_prefixes() {
+        for prefix in &["!", "a.", "?!", "é."] {
+            assert_eq!(validate_prefix(prefix), Ok(()), "{}", prefix);
+        }
+        let longest: String = "!".repeat(MAX_PREFIX_LENGTH);
+        assert_eq!(validate_prefix(&longest), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unusable_prefixes() {
+        let too_long: String = "!".repeat(MAX_PREFIX_LENGTH + 1);
+        for prefix in &[
+            "",
+            too_long.as_str(),
+            " !",
+            "! ",
+            "!\n",
+            "a\tb",
+            "`",
+            "@everyone",
+            "@here",
+            "<@123>",
+            "<#123>",
+        ] {
+            assert!(validate_prefix(prefix).is_err(), "{:?}", prefix);
+        }
+    }
+
+    #[test]
+    fn flags_risky_prefixes() {
+        assert!(risky_prefix("a").is_some());
+        assert!(risky_prefix("bot").is_some());
+        assert!(risky_prefix("*").is_some());
+        assert!(risky_prefix(">").is_some());
+        assert!(risky_prefix("é.").is_some());
+        assert_eq!(risky_prefix("!"), None);
+        assert_eq!(risky_prefix("a."), None);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::core::{
+    structs::{
+        PoolContainer,
+        ReactionMessageCacheContainer,
+    },
+    utils::FancyPool,
+};
+use log::warn;
+use serenity::{
+    model::prelude::MessageId,
+    prelude::Context,
+};
+use std::{
+    collections::HashSet,
+    sync::Arc,
+};
+
+/// Loads the id of every message with reaction roles, a panel or rules, to fill the cache at startup
+pub async fn load_reaction_messages(fancy_db: &FancyPool) -> Result<HashSet<u64>, sqlx::Error> {
+    let mut messages: HashSet<u64> = sqlx::query!("SELECT DISTINCT message_id FROM reaction_roles")
+        .fetch_all(fancy_db.pool())
+        .await?
+        .into_iter()
+        .map(|row| row.message_id as u64)
+        .collect();
+    let panels = sqlx::query!("SELECT message_id FROM reaction_panels")
+        .fetch_all(fancy_db.pool())
+        .await?;
+    messages.extend(panels.into_iter().map(|row| row.message_id as u64));
+    let rules = sqlx::query!("SELECT message_id FROM reaction_role_rules")
+        .fetch_all(fancy_db.pool())
+        .await?;
+    messages.extend(rules.into_iter().map(|row| row.message_id as u64));
+
+    Ok(messages)
+}
+
+/// Returns whether a message might have reaction roles, a panel or rules, so reactions on
+/// and deletions of every other message can be ignored without asking the database.
+/// If the cache isn't there, the message is assumed to have some.
+pub async fn has_reaction_roles(ctx: &Context, message_id: MessageId) -> bool {
+    let cache_lock = {
+        let data = ctx.data.read().await;
+        match data.get::<ReactionMessageCacheContainer>() {
+            Some(cache_lock) => Arc::clone(cache_lock),
+            None => return true,
+        }
+    };
+
+    let cache = cache_lock.read().await;
+    cache.contains(&message_id.0)
+}
+
+/// Checks the database again for whether each message has reaction roles, a panel or rules,
+/// and updates the cache to match.
+/// Anything that adds, moves or removes those needs to call this for the messages it touched.
+///
+/// Messages that can't be checked are kept in the cache, as a message wrongly in it
+/// only costs a database query, while one wrongly missing from it stops its reaction roles working.
+pub async fn refresh_reaction_messages(ctx: &Context, message_ids: &[MessageId]) {
+    let (fancy_db, cache_lock) = {
+        let data = ctx.data.read().await;
+        let fancy_db = data.get::<PoolContainer>().map(Arc::clone);
+        let cache_lock = data.get::<ReactionMessageCacheContainer>().map(Arc::clone);
+        match (fancy_db, cache_lock) {
+            (Some(fancy_db), Some(cache_lock)) => (fancy_db, cache_lock),
+            _ => {
+                warn!("Couldn't get the reaction role cache out of data to refresh it");
+                return;
+            }
+        }
+    };
+
+    for message_id in message_ids {
+        let has_reaction_roles = sqlx::query!(
+            "SELECT (SELECT COUNT(*) FROM reaction_roles WHERE message_id = $1) + (SELECT COUNT(*) FROM reaction_panels WHERE message_id = $1) + (SELECT COUNT(*) FROM reaction_role_rules WHERE message_id = $1) AS count",
+            message_id.0 as i64
+        )
+        .fetch_one(fancy_db.pool())
+        .await
+        .map(|row| row.count.unwrap_or(0) > 0)
+        .unwrap_or_else(|e| {
+            warn!(
+                "Failed to check {} for reaction roles, a panel or rules: {}",
+                message_id, e
+            );
+            true
+        });
+
+        let mut cache = cache_lock.write().await;
+        if has_reaction_roles {
+            cache.insert(message_id.0);
+        } else {
+            cache.remove(&message_id.0);
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use crate::core::{
+    audit_log::{
+        try_audit_log,
+        AuditEntry,
+        AuditOutcome,
+    },
+    error::ReactionError,
+    structs::{
+        PoolContainer,
+        RoleAction,
+        RoleQueueContainer,
+    },
+    utils::FancyPool,
+};
+use log::{
+    error,
+    warn,
+};
+use serenity::{
+    http::error::Error as HttpError,
+    model::prelude::{
+        ChannelId,
+        GuildId,
+        Reaction,
+        RoleId,
+        UserId,
+    },
+    prelude::Context,
+};
+use std::{
+    cmp::min,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
+    time::Duration,
+};
+
+/// How many role changes are taken off the queue at once
+const BATCH_SIZE: i64 = 50;
+/// How many times a change is tried before it's marked as failed
+const MAX_ATTEMPTS: i32 = 8;
+/// How long to wait before the first retry, doubling with every retry after
+const BASE_BACKOFF_SECS: u64 = 5;
+/// The longest a change will wait between retries
+const MAX_BACKOFF_SECS: u64 = 600;
+/// How often the queue is checked for retries that are due, when nothing new has been queued
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+static WORKER_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// The member a queued role change is for, and the reaction behind it when there is one,
+/// which the log channel shows once the change has been made
+pub struct RoleChangeSource {
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+    /// The reaction, formatted so discord renders it
+    pub emoji: Option<String>,
+    pub channel_id: Option<ChannelId>,
+    pub message_id: Option<u64>,
+}
+
+impl RoleChangeSource {
+    /// Makes a source for the member that added or removed a reaction, if it was in a guild
+    pub fn from_reaction(reaction: &Reaction) -> Option<Self> {
+        Some(RoleChangeSource {
+            guild_id: reaction.guild_id?,
+            user_id: reaction.user_id,
+            emoji: Some(reaction.emoji.to_string()),
+            channel_id: Some(reaction.channel_id),
+            message_id: Some(reaction.message_id.0),
+        })
+    }
+}
+
+/// Queues giving or taking away roles from the member that added or removed a reaction,
+/// for the role queue worker to carry out.
+///
+/// Each member's role only keeps its latest change, so reacting and unreacting
+/// quickly replaces the change that's waiting, instead of making both.
+pub async fn queue_roles(
+    ctx: &Context,
+    reaction: &Reaction,
+    role_ids: &[RoleId],
+    action: RoleAction,
+) -> Result<(), ReactionError> {
+    let source = RoleChangeSource::from_reaction(reaction).ok_or(ReactionError::NoGuildId)?;
+    queue_member_roles(ctx, &source, role_ids, action).await
+}
+
+/// Queues giving or taking away roles from a member, like [`queue_roles`],
+/// for changes that don't come straight from a reaction event, like a sync.
+pub async fn queue_member_roles(
+    ctx: &Context,
+    source: &RoleChangeSource,
+    role_ids: &[RoleId],
+    action: RoleAction,
+) -> Result<(), ReactionError> {
+    if role_ids.is_empty() {
+        return Ok(());
+    }
+
+    let (fancy_db, notify) = {
+        let data = ctx.data.read().await;
+        let fancy_db = Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(ReactionError::ShareMapGetError)?,
+        );
+        let notify = data.get::<RoleQueueContainer>().map(Arc::clone);
+        (fancy_db, notify)
+    };
+
+    for role_id in role_ids {
+        let result = sqlx::query!(
+            "INSERT INTO role_queue (guild_id, user_id, role_id, action, channel_id, message_id, emoji) VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (guild_id, user_id, role_id) DO UPDATE
+             SET action = $4, status = 'pending', attempts = 0, last_error = NULL,
+                 next_attempt_at = now(), queued_at = now(), revision = role_queue.revision + 1,
+                 channel_id = $5, message_id = $6, emoji = $7",
+            source.guild_id.0 as i64,
+            source.user_id.0 as i64,
+            role_id.0 as i64,
+            action.as_str(),
+            source.channel_id.map(|channel_id| channel_id.0 as i64),
+            source.message_id.map(|message_id| message_id as i64),
+            source.emoji
+        )
+        .execute(fancy_db.pool())
+        .await;
+        if let Err(e) = result {
+            let entry = AuditEntry {
+                guild_id: source.guild_id,
+                user_id: source.user_id,
+                role_id: *role_id,
+                emoji: source.emoji.clone(),
+                channel_id: source.channel_id,
+                message_id: source.message_id,
+                outcome: AuditOutcome::Failed(format!("Couldn't queue the role change: {}", e)),
+            };
+            try_audit_log(ctx, entry).await;
+            return Err(e.into());
+        }
+    }
+
+    match notify {
+        Some(notify) => notify.notify(),
+        None => warn!(
+            "Couldn't get the role queue out of data, roles will be changed on its next check"
+        ),
+    }
+
+    Ok(())
+}
+
+/// Starts the worker that carries out queued role changes, if it isn't already running
+pub fn start_role_queue(ctx: &Context) {
+    if WORKER_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let (fancy_db, notify) = {
+            let data = ctx.data.read().await;
+            match (
+                data.get::<PoolContainer>().map(Arc::clone),
+                data.get::<RoleQueueContainer>().map(Arc::clone),
+            ) {
+                (Some(fancy_db), Some(notify)) => (fancy_db, notify),
+                _ => {
+                    error!(
+                        "Couldn't get the role queue out of data, queued roles won't be changed"
+                    );
+                    WORKER_STARTED.store(false, Ordering::SeqCst);
+                    return;
+                }
+            }
+        };
+
+        loop {
+            match process_due(&ctx, &fancy_db).await {
+                // A full batch means there's probably more waiting
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => warn!("Failed to process the role queue: {}", e),
+            }
+
+            tokio::select! {
+                _ = notify.notified() => {}
+                _ = tokio::time::delay_for(POLL_INTERVAL) => {}
+            }
+        }
+    });
+}
+
+/// How a failed role change should be handled
+enum Failure {
+    /// Discord is rate limiting us or having trouble, so try again later
+    Retry,
+    /// Trying again won't help, like when the bot is missing permissions
+    Permanent,
+}
+
+/// Sorts a failed role change into ones worth retrying and ones that aren't
+fn classify(error: &serenity::Error) -> Failure {
+    match error {
+        serenity::Error::Http(http_error) => match http_error.as_ref() {
+            HttpError::UnsuccessfulRequest(response) => {
+                let status = response.status_code.as_u16();
+                if status == 429 || status >= 500 {
+                    Failure::Retry
+                } else {
+                    Failure::Permanent
+                }
+            }
+            // The request never got an answer, like a timeout or dropped connection
+            HttpError::Request(_) => Failure::Retry,
+            _ => Failure::Permanent,
+        },
+        _ => Failure::Permanent,
+    }
+}
+
+/// Makes every role change that's due, returning whether a full batch was taken off the queue
+async fn process_due(ctx: &Context, fancy_db: &FancyPool) -> Result<bool, sqlx::Error> {
+    let changes = sqlx::query!(
+        "SELECT guild_id, user_id, role_id, action, attempts, revision, channel_id, message_id, emoji FROM role_queue
+         WHERE status = 'pending' AND next_attempt_at <= now()
+         ORDER BY next_attempt_at LIMIT $1",
+        BATCH_SIZE
+    )
+    .fetch_all(fancy_db.pool())
+    .await?;
+    let full_batch = changes.len() as i64 == BATCH_SIZE;
+
+    for change in changes {
+        let (guild_id, user_id, role_id) = (
+            change.guild_id as u64,
+            change.user_id as u64,
+            change.role_id as u64,
+        );
+        let audit_entry = |outcome| AuditEntry {
+            guild_id: GuildId(guild_id),
+            user_id: UserId(user_id),
+            role_id: RoleId(role_id),
+            emoji: change.emoji.clone(),
+            channel_id: change
+                .channel_id
+                .map(|channel_id| ChannelId(channel_id as u64)),
+            message_id: change.message_id.map(|message_id| message_id as u64),
+            outcome,
+        };
+        let add = change.action == RoleAction::Add.as_str();
+        let result = if add {
+            ctx.http.add_member_role(guild_id, user_id, role_id).await
+        } else {
+            ctx.http
+                .remove_member_role(guild_id, user_id, role_id)
+                .await
+        };
+
+        // Every update checks the revision, in case the change was replaced while it was being made.
+        let error = match result {
+            Ok(()) => {
+                sqlx::query!(
+                    "DELETE FROM role_queue WHERE guild_id = $1 AND user_id = $2 AND role_id = $3 AND revision = $4",
+                    change.guild_id,
+                    change.user_id,
+                    change.role_id,
+                    change.revision
+                )
+                .execute(fancy_db.pool())
+                .await?;
+                let outcome = if add {
+                    AuditOutcome::Added
+                } else {
+                    AuditOutcome::Removed
+                };
+                try_audit_log(ctx, audit_entry(outcome)).await;
+                continue;
+            }
+            Err(e) => e,
+        };
+
+        let attempts = change.attempts + 1;
+        match classify(&error) {
+            Failure::Retry if attempts < MAX_ATTEMPTS => {
+                let backoff = min(
+                    BASE_BACKOFF_SECS.saturating_mul(1 << (attempts - 1)),
+                    MAX_BACKOFF_SECS,
+                );
+                sqlx::query!(
+                    "UPDATE role_queue SET attempts = $5, last_error = $6, next_attempt_at = now() + make_interval(secs => $7)
+                     WHERE guild_id = $1 AND user_id = $2 AND role_id = $3 AND revision = $4",
+                    change.guild_id,
+                    change.user_id,
+                    change.role_id,
+                    change.revision,
+                    attempts,
+                    error.to_string(),
+                    backoff as f64
+                )
+                .execute(fancy_db.pool())
+                .await?;
+            }
+            _ => {
+                warn!(
+                    "Giving up on {} role {} for {} in {}: {}",
+                    change.action, role_id, user_id, guild_id, error
+                );
+                sqlx::query!(
+                    "UPDATE role_queue SET status = 'failed', attempts = $5, last_error = $6
+                     WHERE guild_id = $1 AND user_id = $2 AND role_id = $3 AND revision = $4",
+                    change.guild_id,
+                    change.user_id,
+                    change.role_id,
+                    change.revision,
+                    attempts,
+                    error.to_string()
+                )
+                .execute(fancy_db.pool())
+                .await?;
+                try_audit_log(ctx, audit_entry(AuditOutcome::Failed(error.to_string()))).await;
+            }
+        }
+    }
+
+    Ok(full_batch)
+}
@@ -0,0 +1,435 @@
+use crate::core::{
+    error::SettingsError,
+    error_report::hide_secret,
+    prefixes::{
+        normalize_prefix,
+        validate_prefix,
+    },
+    structs::{
+        PrefixHashMapContainer,
+        SettingsContainer,
+        SettingsFileContainer,
+    },
+};
+use log::{
+    error,
+    info,
+    warn,
+};
+use serde::{
+    de::Error as _,
+    Deserialize,
+    Deserializer,
+};
+use serenity::prelude::Context;
+use std::{
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+/// The file settings are read from when `--config` isn't passed
+pub const SETTINGS_FILE: &str = "settings.toml";
+/// What environment variables overriding settings start with, like ARZTE_DEFAULT_PREFIX
+const ENV_PREFIX: &str = "ARZTE";
+/// Tokens that have been shipped in settings.example.toml, which never belong to a real bot
+const EXAMPLE_TOKENS: &[&str] = &[
+    "MTc1MDQ4MTc0MzMzOTg0NzY4.DxQwWQ.7TYu1RMBpUkTFlkxhYHsqR_89Nw",
+    "your-bot-token",
+];
+/// How often the settings file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+static WATCHER_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Where the settings are read from
+#[derive(Clone, Debug)]
+pub struct SettingsFile {
+    pub path: PathBuf,
+    /// Only a file passed with `--config` has to exist, otherwise everything can come from the environment
+    pub required: bool,
+}
+
+impl SettingsFile {
+    /// Works out the settings file from the command line, which is the one passed
+    /// with `--config <path>`, or settings.toml in the working directory.
+    pub fn from_args() -> Result<Self, SettingsError> {
+        let mut args = std::env::args().skip(1);
+        let mut path = None;
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                path = Some(args.next().ok_or_else(|| {
+                    SettingsError::ArgumentError("`--config` needs a path after it".to_owned())
+                })?);
+            } else if let Some(value) = arg.strip_prefix("--config=") {
+                path = Some(value.to_owned());
+            } else {
+                return Err(SettingsError::ArgumentError(format!(
+                    "`{}` isn't an option, the only option is `--config <path>`",
+                    arg
+                )));
+            }
+        }
+
+        Ok(match path {
+            Some(path) => SettingsFile {
+                path: PathBuf::from(path),
+                required: true,
+            },
+            None => SettingsFile {
+                path: PathBuf::from(SETTINGS_FILE),
+                required: false,
+            },
+        })
+    }
+}
+
+/// A bot token, which is masked whenever it's printed, so it can't end up in logs or error messages
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Token(String);
+
+impl Token {
+    /// The token itself, only for handing to discord
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Token({})", self)
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            write!(f, "<empty>")
+        } else {
+            write!(f, "<masked>")
+        }
+    }
+}
+
+/// Everything that can be set in the settings file, or with an ARZTE_ environment variable
+#[derive(Deserialize, Clone, Debug)]
+pub struct Settings {
+    /// The bot's token, can also be set with DISCORD_TOKEN
+    #[serde(default)]
+    pub token: Token,
+    /// A file the token is read from instead, like a Docker or systemd secret,
+    /// can also be set with DISCORD_TOKEN_FILE
+    #[serde(default)]
+    pub token_file: Option<PathBuf>,
+    /// Whether this is a staging copy of the bot, which errors are reported as
+    #[serde(default)]
+    pub debug: bool,
+    /// The prefix used in guilds that haven't set their own, can also be set with DEFAULT_PREFIX
+    #[serde(default = "default_default_prefix")]
+    pub default_prefix: String,
+    /// How many guilds' prefixes are kept in memory
+    #[serde(default = "default_prefix_cache_size")]
+    pub prefix_cache_size: usize,
+    /// Where panics and errors are sent, they aren't sent to Sentry at all without one
+    #[serde(default)]
+    pub sentry_dsn: Option<String>,
+    /// How much of the errors are sent to Sentry, from 0.0 for none to 1.0 for all of them
+    #[serde(default = "default_sentry_sample_rate")]
+    pub sentry_sample_rate: f32,
+    /// The environment errors are reported under, Production or Staging depending on `debug` if it isn't set
+    #[serde(default)]
+    pub environment: Option<String>,
+    /// A file panics and command errors are written to as JSON, one per line
+    #[serde(default)]
+    pub error_file: Option<PathBuf>,
+    /// Users treated as owners of the bot, on top of whoever owns the application or its team
+    #[serde(default, deserialize_with = "deserialize_ids")]
+    pub owners: Vec<u64>,
+    /// The name shown as the bot's maintainer in `about`, the application's owner or team if it isn't set
+    #[serde(default)]
+    pub maintainer: Option<String>,
+}
+
+fn default_default_prefix() -> String {
+    "a.".to_owned()
+}
+
+fn default_prefix_cache_size() -> usize {
+    10_000
+}
+
+fn default_sentry_sample_rate() -> f32 {
+    1.0
+}
+
+/// Reads a list of ids, either as a list or as one comma separated string,
+/// since environment variables can only hold strings.
+fn deserialize_ids<'de, D>(deserializer: D) -> Result<Vec<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Ids {
+        List(Vec<u64>),
+        Text(String),
+    }
+
+    match Ids::deserialize(deserializer)? {
+        Ids::List(ids) => Ok(ids),
+        Ids::Text(text) => text
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| {
+                id.parse()
+                    .map_err(|_| D::Error::custom(format!("`{}` isn't a user id", id)))
+            })
+            .collect(),
+    }
+}
+
+impl Settings {
+    /// Reads the settings from a file, applying the overrides from the environment,
+    /// and checks that every setting can be used.
+    ///
+    /// Any setting can be overridden with an environment variable named after it,
+    /// like ARZTE_PREFIX_CACHE_SIZE. DISCORD_TOKEN and DEFAULT_PREFIX still work too,
+    /// but lose to ARZTE_TOKEN and ARZTE_DEFAULT_PREFIX.
+    pub fn load(file: &SettingsFile) -> Result<Self, SettingsError> {
+        let mut config = config::Config::default();
+        config.merge(config::File::from(file.path.as_path()).required(file.required))?;
+        config.merge(config::Environment::with_prefix(ENV_PREFIX))?;
+        let mut settings: Settings = config.try_into()?;
+
+        let mut token_from_env = env_override_set("token");
+        if !token_from_env {
+            if let Ok(token) = std::env::var("DISCORD_TOKEN") {
+                settings.token = Token(token);
+                token_from_env = true;
+            }
+        }
+        if !env_override_set("token_file") {
+            if let Some(path) = std::env::var_os("DISCORD_TOKEN_FILE") {
+                settings.token_file = Some(PathBuf::from(path));
+            }
+        }
+        // A token set in the environment is the most specific, otherwise the file wins over the settings
+        if let (Some(path), false) = (&settings.token_file, token_from_env) {
+            let token = std::fs::read_to_string(path)
+                .map_err(|e| SettingsError::TokenFileError(path.display().to_string(), e))?;
+            settings.token = Token(token.trim().to_owned());
+        }
+        // Lets a second copy of the bot, like a staging one, share servers without both answering every command
+        if !env_override_set("default_prefix") {
+            if let Ok(prefix) = std::env::var("DEFAULT_PREFIX") {
+                settings.default_prefix = prefix;
+            }
+        }
+        settings.default_prefix = normalize_prefix(&settings.default_prefix);
+
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Checks every setting, listing everything that's wrong rather than stopping at the first
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let mut problems = Vec::new();
+
+        let token = self.token.expose();
+        if token.trim().is_empty() {
+            problems.push(
+                "`token`: no token was set, either in the file, with `token_file` or with ARZTE_TOKEN"
+                    .to_owned(),
+            );
+        } else if EXAMPLE_TOKENS.contains(&token.trim()) {
+            problems.push(
+                "`token`: is the example token from settings.example.toml, set it to your own bot's token"
+                    .to_owned(),
+            );
+        } else if token.trim() != token {
+            problems.push("`token`: has spaces or new lines around it".to_owned());
+        }
+        if let Err(reason) = validate_prefix(&self.default_prefix) {
+            problems.push(format!(
+                "`default_prefix`: ``{}`` can't be used, {}",
+                self.default_prefix, reason
+            ));
+        }
+        if self.prefix_cache_size < 1 {
+            problems.push("`prefix_cache_size`: has to be at least 1".to_owned());
+        }
+        if let Some(dsn) = &self.sentry_dsn {
+            if let Err(e) = dsn.parse::<sentry::types::Dsn>() {
+                problems.push(format!("`sentry_dsn`: isn't a valid DSN, {}", e));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.sentry_sample_rate) {
+            problems.push("`sentry_sample_rate`: has to be between 0.0 and 1.0".to_owned());
+        }
+        if self.owners.contains(&0) {
+            problems.push("`owners`: 0 isn't a user id".to_owned());
+        }
+        if let Some(maintainer) = &self.maintainer {
+            if maintainer.trim().is_empty() {
+                problems.push(
+                    "`maintainer`: can't be empty, leave it out to show the application's owner"
+                        .to_owned(),
+                );
+            }
+        }
+        if let Some(environment) = &self.environment {
+            if environment.trim().is_empty() {
+                problems.push(
+                    "`environment`: can't be empty, leave it out to use the default".to_owned(),
+                );
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(SettingsError::Invalid(problems))
+        }
+    }
+
+    /// The environment errors are reported under
+    pub fn environment(&self) -> String {
+        match &self.environment {
+            Some(environment) => environment.clone(),
+            None if self.debug => "Staging".to_owned(),
+            None => "Production".to_owned(),
+        }
+    }
+}
+
+/// Reads the settings file again and swaps the new settings in, leaving the old ones
+/// in place if the new ones aren't valid.
+///
+/// Returns the settings that changed but only take effect once the bot is restarted.
+pub async fn reload_settings(ctx: &Context) -> Result<Vec<&'static str>, SettingsError> {
+    let file = settings_file(ctx).await?;
+    let new_settings = tokio::task::spawn_blocking(move || Settings::load(&file)).await??;
+
+    let (settings_lock, cache_lock) = {
+        let data = ctx.data.read().await;
+        let settings_lock = Arc::clone(
+            data.get::<SettingsContainer>()
+                .ok_or(SettingsError::ShareMapGetError)?,
+        );
+        let cache_lock = Arc::clone(
+            data.get::<PrefixHashMapContainer>()
+                .ok_or(SettingsError::ShareMapGetError)?,
+        );
+        (settings_lock, cache_lock)
+    };
+
+    let mut settings = settings_lock.write().await;
+    let mut needs_restart = Vec::new();
+    if settings.token != new_settings.token {
+        // The new token is only used after a restart, but it can show up in errors before then
+        hide_secret(new_settings.token.expose());
+        needs_restart.push("token");
+    }
+    if settings.environment() != new_settings.environment() {
+        needs_restart.push("environment");
+    }
+    if settings.sentry_dsn != new_settings.sentry_dsn {
+        needs_restart.push("sentry_dsn");
+    }
+    if (settings.sentry_sample_rate - new_settings.sentry_sample_rate).abs() > f32::EPSILON {
+        needs_restart.push("sentry_sample_rate");
+    }
+    if settings.error_file != new_settings.error_file {
+        needs_restart.push("error_file");
+    }
+    if settings.owners != new_settings.owners {
+        needs_restart.push("owners");
+    }
+    if settings.prefix_cache_size != new_settings.prefix_cache_size {
+        cache_lock
+            .lock()
+            .await
+            .resize(new_settings.prefix_cache_size);
+    }
+    *settings = new_settings;
+
+    Ok(needs_restart)
+}
+
+/// Starts watching the settings file, reloading the settings whenever it changes,
+/// if it isn't already being watched.
+pub fn start_settings_watcher(ctx: &Context) {
+    if WATCHER_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let file = match settings_file(&ctx).await {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Couldn't watch the settings file: {}", e);
+                WATCHER_STARTED.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+
+        let mut last_modified = modified_at(&file.path);
+        loop {
+            tokio::time::delay_for(WATCH_INTERVAL).await;
+
+            let modified = modified_at(&file.path);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            let path = file.path.display();
+            match reload_settings(&ctx).await {
+                Ok(needs_restart) if needs_restart.is_empty() => {
+                    info!("Reloaded the settings after {} changed", path)
+                }
+                Ok(needs_restart) => warn!(
+                    "Reloaded the settings after {} changed, but {} only change after a restart",
+                    path,
+                    needs_restart.join(", ")
+                ),
+                Err(e) => error!("Kept the old settings after {} changed: {}", path, e),
+            }
+        }
+    });
+}
+
+/// Returns where the settings were read from when the bot started
+pub async fn settings_file(ctx: &Context) -> Result<SettingsFile, SettingsError> {
+    let data = ctx.data.read().await;
+    data.get::<SettingsFileContainer>()
+        .cloned()
+        .ok_or(SettingsError::ShareMapGetError)
+}
+
+/// Whether a setting has been overridden with its ARZTE_ environment variable
+fn env_override_set(key: &str) -> bool {
+    std::env::var_os(format!("{}_{}", ENV_PREFIX, key.to_uppercase())).is_some()
+}
+
+/// When a file was last changed, or `None` if it can't be read
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
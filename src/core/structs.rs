@@ -87,45 +87,138 @@ pub struct Commit {
 
 // This is the struct and implementation for a ShardManager Container,
 // which allows for non serenity items to access the shardmanger,
-use super::utils::FancyPool;
+use super::{
+    settings::{
+        Settings,
+        SettingsFile,
+    },
+    utils::FancyPool,
+};
+use lru::LruCache;
 use serenity::client::bridge::gateway::ShardManager;
-use serenity::prelude::Mutex as SernMutex;
+use serenity::prelude::{
+    Mutex as SernMutex,
+    RwLock,
+    TypeMapKey,
+};
 use std::{
-    collections::HashMap,
-    sync::{
-        Arc,
-        Mutex,
-    },
+    collections::HashSet,
+    sync::Arc,
 };
-use tokio::runtime::Runtime;
-use typemap::Key;
+use tokio::sync::Notify;
 
 pub struct ShardManagerContainer;
 
-impl Key for ShardManagerContainer {
+impl TypeMapKey for ShardManagerContainer {
     type Value = Arc<SernMutex<ShardManager>>;
 }
 
+/// The current settings, which are swapped out whenever they're reloaded
 pub struct SettingsContainer;
 
-impl Key for SettingsContainer {
-    type Value = Arc<Mutex<config::Config>>;
+impl TypeMapKey for SettingsContainer {
+    type Value = Arc<RwLock<Settings>>;
 }
 
-pub struct TokioContainer;
+/// Where the settings are read from, so they can be read again
+pub struct SettingsFileContainer;
 
-impl Key for TokioContainer {
-    type Value = Arc<Mutex<Runtime>>;
+impl TypeMapKey for SettingsFileContainer {
+    type Value = SettingsFile;
 }
 
 pub struct PoolContainer;
 
-impl Key for PoolContainer {
+impl TypeMapKey for PoolContainer {
     type Value = Arc<FancyPool>;
 }
 
+/// Each guild's prefixes, with an empty list for guilds using the default prefix.
+/// Only the most recently used guilds are kept, up to the `prefix_cache_size` setting.
 pub struct PrefixHashMapContainer;
 
-impl Key for PrefixHashMapContainer {
-    type Value = Arc<Mutex<HashMap<u64, String>>>;
+impl TypeMapKey for PrefixHashMapContainer {
+    type Value = Arc<SernMutex<LruCache<u64, Vec<String>>>>;
+}
+
+/// The ids of every message with reaction roles, a panel or rules
+pub struct ReactionMessageCacheContainer;
+
+impl TypeMapKey for ReactionMessageCacheContainer {
+    type Value = Arc<RwLock<HashSet<u64>>>;
+}
+
+/// Wakes up the role queue worker when a role change is queued
+pub struct RoleQueueContainer;
+
+impl TypeMapKey for RoleQueueContainer {
+    type Value = Arc<Notify>;
+}
+
+/// How a reaction role behaves when its reaction is added or removed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReactionMode {
+    /// Adding the reaction gives the role, removing it takes the role away
+    Toggle,
+    /// Adding the reaction gives the role, removing it does nothing,
+    /// for things like accepting the rules
+    AddOnly,
+    /// Adding the reaction takes the role away, removing it does nothing,
+    /// for opt out panels
+    RemoveOnly,
+    /// Adding the reaction gives the role, then the reaction is removed
+    /// so the message stays clean
+    Verify,
+}
+
+impl ReactionMode {
+    /// The name this mode is stored under in the database
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReactionMode::Toggle => "toggle",
+            ReactionMode::AddOnly => "add_only",
+            ReactionMode::RemoveOnly => "remove_only",
+            ReactionMode::Verify => "verify",
+        }
+    }
+}
+
+impl Default for ReactionMode {
+    fn default() -> Self {
+        ReactionMode::Toggle
+    }
+}
+
+impl std::str::FromStr for ReactionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "toggle" => Ok(ReactionMode::Toggle),
+            "add_only" => Ok(ReactionMode::AddOnly),
+            "remove_only" => Ok(ReactionMode::RemoveOnly),
+            "verify" => Ok(ReactionMode::Verify),
+            _ => Err(format!(
+                "`{}` isn't a reaction role mode, valid modes are `toggle`, `add_only`, `remove_only` and `verify`",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether a queued role change gives or takes away the role
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoleAction {
+    Add,
+    Remove,
+}
+
+impl RoleAction {
+    /// The name this action is stored under in the database
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RoleAction::Add => "add",
+            RoleAction::Remove => "remove",
+        }
+    }
 }
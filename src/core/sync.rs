@@ -0,0 +1,471 @@
+use crate::{
+    core::{
+        error::ReactionError,
+        events::reaction_add::{
+            rule_violation,
+            Binding,
+        },
+        role_queue::{
+            queue_member_roles,
+            RoleChangeSource,
+        },
+        structs::{
+            ReactionMode,
+            RoleAction,
+        },
+        utils::reaction_type_from_row,
+    },
+    PoolContainer,
+};
+use lazy_static::lazy_static;
+use log::{
+    info,
+    warn,
+};
+use serenity::{
+    model::prelude::{
+        ChannelId,
+        GuildId,
+        ReactionType,
+        RoleId,
+        UserId,
+    },
+    prelude::Context,
+};
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+        HashSet,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+/// Discord won't return more than this many users per request for a reaction
+const REACTION_USERS_PAGE_SIZE: u8 = 100;
+
+lazy_static! {
+    /// Guilds with a sync currently running, so the same guild isn't synced twice at once
+    static ref SYNCING_GUILDS: Mutex<HashSet<GuildId>> = Mutex::new(HashSet::new());
+}
+
+/// What a sync changed
+#[derive(Default, Debug)]
+pub struct SyncReport {
+    pub messages: usize,
+    /// Roles queued to be given out
+    pub roles_added: usize,
+    /// Roles queued to be taken away
+    pub roles_removed: usize,
+    /// Reactions that broke their message's rules, so didn't give out anything
+    pub rejected: usize,
+    pub failures: usize,
+}
+
+/// A message's reaction, and the reaction roles on it
+struct SyncReaction {
+    channel_id: u64,
+    message_id: u64,
+    /// The reaction's key in the `emoji` column of reaction_roles
+    emoji: String,
+    reaction_type: ReactionType,
+    bindings: Vec<Binding>,
+}
+
+/// Walks every reaction role message in the given guilds, paging through who
+/// reacted to each one, and queues giving or taking away roles to match the reactions.
+/// This catches up on anything that happened while the bot was offline.
+///
+/// Since a toggle reaction role means "has the role if and only if they've reacted",
+/// members that have the role without reacting get it taken away, unless the role
+/// is also given out by a reaction role in any other mode.
+///
+/// Reactions are checked against their message's rules like they are when the bot is online,
+/// so prerequisite roles, role limits and exclusive groups still apply.
+///
+/// Guilds that are already being synced are skipped.
+pub async fn sync_reaction_roles(
+    ctx: &Context,
+    guild_ids: &[GuildId],
+) -> Result<SyncReport, ReactionError> {
+    let mut report = SyncReport::default();
+
+    for guild_id in guild_ids {
+        if !SYNCING_GUILDS
+            .lock()
+            .map_err(|_| ReactionError::LockError)?
+            .insert(*guild_id)
+        {
+            continue;
+        }
+        let result = sync(ctx, *guild_id, &mut report).await;
+        SYNCING_GUILDS
+            .lock()
+            .map_err(|_| ReactionError::LockError)?
+            .remove(guild_id);
+        result?;
+    }
+
+    info!(
+        "Reaction role sync finished: {} messages checked, {} roles queued to be added, {} roles queued to be removed, {} reactions rejected, {} failures",
+        report.messages, report.roles_added, report.roles_removed, report.rejected, report.failures
+    );
+
+    Ok(report)
+}
+
+async fn sync(
+    ctx: &Context,
+    guild_id: GuildId,
+    report: &mut SyncReport,
+) -> Result<(), ReactionError> {
+    let fancy_db = {
+        let data = ctx.data.read().await;
+        Arc::clone(
+            data.get::<PoolContainer>()
+                .ok_or(ReactionError::ShareMapGetError)?,
+        )
+    };
+
+    let rows = sqlx::query!(
+        "SELECT channel_id, message_id, emoji, name, emoji_id, role_id, mode, exclusive_group, required_role_id FROM reaction_roles WHERE guild_id = $1 AND channel_id IS NOT NULL",
+        guild_id.0 as i64
+    )
+    .fetch_all(fancy_db.pool())
+    .await?;
+    let max_roles: HashMap<u64, i32> = sqlx::query!(
+        "SELECT message_id, max_roles FROM reaction_role_rules WHERE guild_id = $1",
+        guild_id.0 as i64
+    )
+    .fetch_all(fancy_db.pool())
+    .await?
+    .into_iter()
+    .filter_map(|rules| Some((rules.message_id as u64, rules.max_roles?)))
+    .collect();
+
+    // Group the reaction roles by each reaction on each message.
+    let mut reactions: BTreeMap<(u64, String), SyncReaction> = BTreeMap::new();
+    for row in rows {
+        let channel_id = match row.channel_id {
+            Some(channel_id) => channel_id as u64,
+            None => continue,
+        };
+        reactions
+            .entry((row.message_id as u64, row.emoji.clone()))
+            .or_insert_with(|| SyncReaction {
+                channel_id,
+                message_id: row.message_id as u64,
+                emoji: row.emoji.clone(),
+                reaction_type: reaction_type_from_row(&row.name, row.emoji_id),
+                bindings: Vec::new(),
+            })
+            .bindings
+            .push(Binding {
+                role_id: RoleId(row.role_id as u64),
+                emoji: row.emoji,
+                exclusive_group: row.exclusive_group,
+                mode: row.mode.parse::<ReactionMode>().unwrap_or_default(),
+                required_role_id: row.required_role_id.map(|id| RoleId(id as u64)),
+            });
+    }
+    let reactions: Vec<SyncReaction> = reactions
+        .into_iter()
+        .map(|(_, reaction)| reaction)
+        .collect();
+
+    sync_guild(ctx, guild_id, &reactions, &max_roles, report).await;
+
+    Ok(())
+}
+
+/// A reaction that was loaded, along with who reacted and what's needed to check them against the message's rules
+struct Reacted<'a> {
+    reaction: &'a SyncReaction,
+    reactors: HashSet<UserId>,
+    /// The reaction roles on the message's other reactions
+    other_bindings: Vec<Binding>,
+    /// The exclusive groups picking this reaction's roles would pick from
+    groups: HashSet<String>,
+}
+
+async fn sync_guild(
+    ctx: &Context,
+    guild_id: GuildId,
+    reactions: &[SyncReaction],
+    max_roles: &HashMap<u64, i32>,
+    report: &mut SyncReport,
+) {
+    let mut loaded: Vec<Reacted> = Vec::new();
+    // Who should lose each role, along with the reaction behind it, if there is one
+    let mut revokes: HashMap<UserId, HashMap<RoleId, Option<&SyncReaction>>> = HashMap::new();
+    let mut toggle_reactors: HashMap<RoleId, HashSet<UserId>> = HashMap::new();
+    let mut other_roles: HashSet<RoleId> = HashSet::new();
+    // Roles on reactions that couldn't be loaded, so who reacted for them isn't fully known
+    let mut unknown_roles: HashSet<RoleId> = HashSet::new();
+    let mut checked_messages: HashSet<u64> = HashSet::new();
+
+    for reaction in reactions {
+        if checked_messages.insert(reaction.message_id) {
+            report.messages += 1;
+        }
+
+        let reactors = match reaction_users(ctx, reaction).await {
+            Ok(reactors) => reactors,
+            Err(e) => {
+                warn!(
+                    "Couldn't get reactions on message {} while syncing: {}",
+                    reaction.message_id, e
+                );
+                report.failures += 1;
+                unknown_roles.extend(reaction.bindings.iter().map(|binding| binding.role_id));
+                continue;
+            }
+        };
+
+        for binding in &reaction.bindings {
+            match binding.mode {
+                ReactionMode::Toggle => {
+                    toggle_reactors
+                        .entry(binding.role_id)
+                        .or_default()
+                        .extend(reactors.iter().copied());
+                }
+                _ => {
+                    other_roles.insert(binding.role_id);
+                }
+            }
+
+            if binding.mode == ReactionMode::RemoveOnly {
+                for user_id in &reactors {
+                    revokes
+                        .entry(*user_id)
+                        .or_default()
+                        .insert(binding.role_id, Some(reaction));
+                }
+            }
+        }
+
+        // Verify reactions are meant to be removed once they've done their job.
+        if reaction
+            .bindings
+            .iter()
+            .any(|binding| binding.mode == ReactionMode::Verify)
+        {
+            for user_id in &reactors {
+                if let Err(e) = ctx
+                    .http
+                    .delete_reaction(
+                        reaction.channel_id,
+                        reaction.message_id,
+                        Some(user_id.0),
+                        &reaction.reaction_type,
+                    )
+                    .await
+                {
+                    warn!("Failed to remove verify reaction while syncing: {}", e);
+                }
+            }
+        }
+
+        loaded.push(Reacted {
+            reaction,
+            reactors,
+            other_bindings: reactions
+                .iter()
+                .filter(|other| {
+                    other.message_id == reaction.message_id && other.emoji != reaction.emoji
+                })
+                .flat_map(|other| other.bindings.iter().cloned())
+                .collect(),
+            groups: reaction
+                .bindings
+                .iter()
+                .filter(|binding| binding.mode != ReactionMode::RemoveOnly)
+                .filter_map(|binding| binding.exclusive_group.clone())
+                .collect(),
+        });
+    }
+
+    // Anyone holding a toggle role that hasn't reacted for it loses it.
+    let members = ctx
+        .cache
+        .guild_field(guild_id, |guild| {
+            guild
+                .members
+                .iter()
+                .map(|(user_id, member)| (*user_id, member.roles.clone()))
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+    // Roles with reactions that couldn't be loaded are skipped too, or everyone who reacted
+    // on those would lose their role because of a failed request.
+    for (role_id, reactors) in &toggle_reactors {
+        if other_roles.contains(role_id) || unknown_roles.contains(role_id) {
+            continue;
+        }
+        for (user_id, roles) in &members {
+            if roles.contains(role_id) && !reactors.contains(user_id) {
+                revokes
+                    .entry(*user_id)
+                    .or_default()
+                    .entry(*role_id)
+                    .or_insert(None);
+            }
+        }
+    }
+
+    // Every role in each exclusive group, which can span several messages
+    let mut group_roles: HashMap<&str, HashSet<RoleId>> = HashMap::new();
+    for binding in reactions.iter().flat_map(|reaction| &reaction.bindings) {
+        if binding.mode == ReactionMode::RemoveOnly {
+            continue;
+        }
+        if let Some(group) = &binding.exclusive_group {
+            group_roles
+                .entry(group.as_str())
+                .or_default()
+                .insert(binding.role_id);
+        }
+    }
+
+    let users: HashSet<UserId> = loaded
+        .iter()
+        .flat_map(|reacted| reacted.reactors.iter().copied())
+        .chain(revokes.keys().copied())
+        .collect();
+    for user_id in users {
+        let member = match guild_id.member(ctx, user_id).await {
+            Ok(member) => member,
+            Err(_) => continue, // They've left the guild since reacting
+        };
+
+        let no_roles = HashMap::new();
+        let revoked = revokes.get(&user_id).unwrap_or(&no_roles);
+        // The member's roles as they'll be once the sync is done, so each reaction can be checked
+        // against its message's rules in turn, the same way a reaction is when the bot is online.
+        let mut roles: Vec<RoleId> = member
+            .roles
+            .iter()
+            .filter(|role_id| !revoked.contains_key(*role_id))
+            .copied()
+            .collect();
+        let mut granted: HashMap<RoleId, &SyncReaction> = HashMap::new();
+        for reacted in &loaded {
+            if !reacted.reactors.contains(&user_id) {
+                continue;
+            }
+            let reaction = reacted.reaction;
+
+            // Which of a group's reactions was picked last can't be told after the fact,
+            // so a member that already has one of the group's roles keeps it instead.
+            let picked_elsewhere = reacted.groups.iter().any(|group| {
+                group_roles
+                    .get(group.as_str())
+                    .map_or(false, |group_roles| {
+                        roles.iter().any(|role_id| {
+                            group_roles.contains(role_id)
+                                && !reaction
+                                    .bindings
+                                    .iter()
+                                    .any(|binding| binding.role_id == *role_id)
+                        })
+                    })
+            });
+            let violation = rule_violation(
+                ctx,
+                &roles,
+                &reaction.bindings,
+                &reacted.other_bindings,
+                &reacted.groups,
+                max_roles.get(&reaction.message_id).copied(),
+            )
+            .await;
+            if picked_elsewhere || violation.is_some() {
+                report.rejected += 1;
+                continue;
+            }
+
+            for binding in &reaction.bindings {
+                if binding.mode == ReactionMode::RemoveOnly {
+                    continue;
+                }
+                if !roles.contains(&binding.role_id) {
+                    roles.push(binding.role_id);
+                }
+                granted.entry(binding.role_id).or_insert(reaction);
+            }
+        }
+
+        let to_add = granted
+            .iter()
+            .filter(|(role_id, _)| !member.roles.contains(role_id))
+            .map(|(role_id, reaction)| (*role_id, Some(*reaction), RoleAction::Add));
+        // If one reaction gives a role that another takes away, giving it wins.
+        let to_remove = revoked
+            .iter()
+            .filter(|(role_id, _)| member.roles.contains(role_id))
+            .filter(|(role_id, _)| !granted.contains_key(*role_id))
+            .map(|(role_id, reaction)| (*role_id, *reaction, RoleAction::Remove));
+
+        // The changes go through the role queue, so they're retried and audited like any other.
+        for (role_id, reaction, action) in to_add.chain(to_remove) {
+            let source = RoleChangeSource {
+                guild_id,
+                user_id,
+                emoji: reaction.map(|reaction| reaction.reaction_type.to_string()),
+                channel_id: reaction.map(|reaction| ChannelId(reaction.channel_id)),
+                message_id: reaction.map(|reaction| reaction.message_id),
+            };
+            match queue_member_roles(ctx, &source, &[role_id], action).await {
+                Ok(()) if action == RoleAction::Add => report.roles_added += 1,
+                Ok(()) => report.roles_removed += 1,
+                Err(e) => {
+                    warn!(
+                        "Failed to queue role {} for {} while syncing: {}",
+                        role_id, user_id, e
+                    );
+                    report.failures += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Pages through everyone that's added a reaction to a message, leaving out bots
+async fn reaction_users(
+    ctx: &Context,
+    reaction: &SyncReaction,
+) -> Result<HashSet<UserId>, serenity::Error> {
+    let mut reactors = HashSet::new();
+    let mut after = None;
+
+    loop {
+        let users = ctx
+            .http
+            .get_reaction_users(
+                reaction.channel_id,
+                reaction.message_id,
+                &reaction.reaction_type,
+                REACTION_USERS_PAGE_SIZE,
+                after,
+            )
+            .await?;
+        let page_size = users.len();
+        after = users.last().map(|user| user.id.0);
+
+        reactors.extend(
+            users
+                .into_iter()
+                .filter(|user| !user.bot)
+                .map(|user| user.id),
+        );
+
+        if page_size < REACTION_USERS_PAGE_SIZE as usize {
+            return Ok(reactors);
+        }
+    }
+}
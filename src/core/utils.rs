@@ -1,13 +1,15 @@
+use serenity::{
+    http::error::Error as HttpError,
+    model::{
+        id::EmojiId,
+        prelude::ReactionType,
+    },
+};
 use sqlx::{
     PgConnection,
     PgPool,
     Pool,
 };
-use std::sync::{
-    Arc,
-    Mutex,
-};
-use tokio::runtime::Runtime;
 
 // Struct to hold the database pool so it can be wrapped
 // in an Arc.
@@ -20,19 +22,13 @@ pub struct FancyPool {
 }
 
 impl FancyPool {
-    /// Returns a new instance of FancyPool, using the tokio runtime
-    pub fn new(runtime: Arc<Mutex<Runtime>>) -> Self {
-        let pool = runtime
-            .try_lock()
-            .expect("Unable to get runtime lock to start database pool")
-            .block_on(async {
-                PgPool::new(
-                    &std::env::var("DATABASE_URL")
-                        .expect("DATABASE_URL enviroment variable not set"),
-                )
-                .await
-                .expect("unable to connect to db")
-            });
+    /// Returns a new instance of FancyPool, connected to the database in `DATABASE_URL`
+    pub async fn new() -> Self {
+        let pool = PgPool::new(
+            &std::env::var("DATABASE_URL").expect("DATABASE_URL enviroment variable not set"),
+        )
+        .await
+        .expect("unable to connect to db");
         FancyPool {
             postgres_pool: pool,
         }
@@ -43,3 +39,58 @@ impl FancyPool {
         &self.postgres_pool
     }
 }
+
+/// Returns a link that jumps to the given message
+pub fn message_url(guild_id: u64, channel_id: u64, message_id: u64) -> String {
+    format!(
+        "https://discordapp.com/channels/{}/{}/{}",
+        guild_id, channel_id, message_id
+    )
+}
+
+/// Turns the `name` and `emoji_id` columns of a reaction_roles row
+/// back into the reaction they were created from.
+pub fn reaction_type_from_row(name: &str, emoji_id: Option<i64>) -> ReactionType {
+    match emoji_id {
+        Some(id) => ReactionType::Custom {
+            animated: false,
+            id: EmojiId(id as u64),
+            name: Some(name.to_owned()),
+        },
+        None => ReactionType::Unicode(name.to_owned()),
+    }
+}
+
+/// Formats the `name` and `emoji_id` columns of a reaction_roles row
+/// so that discord will render the emoji in a message.
+pub fn emoji_display(name: &str, emoji_id: Option<i64>) -> String {
+    match emoji_id {
+        Some(id) => format!("<:{}:{}>", name, id),
+        None => name.to_owned(),
+    }
+}
+
+/// Returns the key a reaction is stored under in the `emoji` column of reaction_roles,
+/// which is the emoji's id for custom emojis, or the emoji itself for unicode emojis.
+pub fn emoji_key(reaction: &ReactionType) -> Option<String> {
+    match reaction {
+        ReactionType::Custom { id, .. } => Some(id.0.to_string()),
+        ReactionType::Unicode(name) => Some(name.clone()),
+        _ => None, // We don't know reaction type this is, so there's no key for it.
+    }
+}
+
+/// Returns true if discord refused a request because the bot can't see the thing asked for,
+/// or it doesn't exist anymore, as opposed to discord having problems of its own.
+pub fn is_missing_access(error: &serenity::Error) -> bool {
+    match error {
+        serenity::Error::Http(http_error) => match http_error.as_ref() {
+            HttpError::UnsuccessfulRequest(response) => {
+                let status = response.status_code.as_u16();
+                status == 403 || status == 404
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
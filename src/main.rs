@@ -7,19 +7,23 @@ pub mod core;
 
 #[allow(unused_imports)]
 use log::{
+    debug,
     error,
     info,
     trace,
     warn,
 };
 
+use lru::LruCache;
 use serenity::{
+    async_trait,
     framework::{
         standard::{
             help_commands,
             macros::{
                 group,
                 help,
+                hook,
             },
             Args,
             CommandGroup,
@@ -29,15 +33,20 @@ use serenity::{
         },
         StandardFramework,
     },
+    http::Http,
     model::{
+        application::MembershipState,
         event::ResumedEvent,
         gateway::Ready,
+        guild::GuildUnavailable,
         prelude::*,
     },
     prelude::{
         Client,
         Context,
         EventHandler,
+        Mutex as SernMutex,
+        RwLock,
     },
 };
 use std::{
@@ -46,48 +55,165 @@ use std::{
         HashSet,
     },
     sync::Arc,
-    sync::Mutex,
 };
+use tokio::sync::Notify;
 
 use crate::{
     commands::{
         admin::*,
+        config::*,
         info::*,
         math::*,
         owner::*,
+        panel::*,
     },
     core::{
         error::ReactionError,
+        error_report::{
+            hide_secret,
+            redact,
+            report_error,
+            start_error_file,
+            ErrorRecord,
+        },
         events::{
+            guild_delete::guild_delete,
+            guild_emojis_update::guild_emojis_update,
+            guild_role_delete::guild_role_delete,
+            message_delete::message_delete,
             reaction_add::reaction_add,
             reaction_remove::reaction_remove,
         },
+        prefixes::{
+            default_prefix,
+            describe_prefixes,
+            guild_prefixes,
+            matching_prefix,
+            warm_prefix_cache,
+        },
+        reaction_cache::load_reaction_messages,
+        role_queue::start_role_queue,
+        settings::{
+            start_settings_watcher,
+            Settings,
+            SettingsFile,
+        },
         structs::{
             PoolContainer,
             PrefixHashMapContainer,
+            ReactionMessageCacheContainer,
+            RoleQueueContainer,
             SettingsContainer,
+            SettingsFileContainer,
             ShardManagerContainer,
-            TokioContainer,
         },
+        sync::sync_reaction_roles,
         utils::FancyPool,
     },
 };
 
 struct Handler;
 
+#[async_trait]
 impl EventHandler for Handler {
-    fn ready(&self, _: Context, ready: Ready) {
+    async fn ready(&self, ctx: Context, ready: Ready) {
         info!("Connected as {}", ready.user.name);
+        start_role_queue(&ctx);
+        start_settings_watcher(&ctx);
+
+        let guilds: Vec<GuildId> = ready.guilds.iter().map(|guild| guild.id()).collect();
+        tokio::spawn(async move {
+            match warm_prefix_cache(&ctx, &guilds).await {
+                Ok(warmed) => info!("Cached the prefixes of {} guild(s)", warmed),
+                Err(e) => warn!("Failed to warm the prefix cache on ready: {}", e),
+            }
+        });
     }
 
-    fn resume(&self, _: Context, _: ResumedEvent) {
+    // Ready is sent before any of the guilds are, so the missed reactions are caught up on
+    // once every guild from ready is in the cache, rather than on ready itself.
+    async fn cache_ready(&self, ctx: Context, guilds: Vec<GuildId>) {
+        tokio::spawn(async move {
+            if let Err(e) = sync_reaction_roles(&ctx, &guilds).await {
+                error!("Failed to sync reaction roles on ready: {}", e);
+            }
+        });
+    }
+
+    async fn resume(&self, ctx: Context, _: ResumedEvent) {
         info!("Resumed");
+        tokio::spawn(async move {
+            // Only sync the guilds that are on the shard that resumed
+            let shard_count = ctx.cache.shard_count().await;
+            let guilds: Vec<GuildId> = ctx
+                .cache
+                .guilds()
+                .await
+                .into_iter()
+                .filter(|guild_id| (guild_id.0 >> 22) % shard_count == ctx.shard_id)
+                .collect();
+            if let Err(e) = sync_reaction_roles(&ctx, &guilds).await {
+                error!("Failed to sync reaction roles on resume: {}", e);
+            }
+        });
+    }
+
+    async fn message_delete(&self, ctx: Context, _: ChannelId, message_id: MessageId) {
+        if let Err(e) = message_delete(&ctx, message_id).await {
+            error!(
+                "Failed to clean up after deleted message {}: {}",
+                message_id, e
+            );
+        }
+    }
+
+    async fn message_delete_bulk(&self, ctx: Context, _: ChannelId, message_ids: Vec<MessageId>) {
+        for message_id in message_ids {
+            if let Err(e) = message_delete(&ctx, message_id).await {
+                error!(
+                    "Failed to clean up after deleted message {}: {}",
+                    message_id, e
+                );
+            }
+        }
+    }
+
+    async fn guild_role_delete(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        role_id: RoleId,
+        role: Option<Role>,
+    ) {
+        if let Err(e) = guild_role_delete(&ctx, guild_id, role_id, role.as_ref()).await {
+            error!("Failed to clean up after deleted role {}: {}", role_id, e);
+        }
+    }
+
+    async fn guild_emojis_update(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        current_state: HashMap<EmojiId, Emoji>,
+    ) {
+        if let Err(e) = guild_emojis_update(&ctx, guild_id, &current_state).await {
+            error!(
+                "Failed to clean up after deleted emojis in {}: {}",
+                guild_id, e
+            );
+        }
+    }
+
+    async fn guild_delete(&self, ctx: Context, guild: GuildUnavailable, _: Option<Guild>) {
+        if let Err(e) = guild_delete(&ctx, guild.id).await {
+            error!("Failed to clean up after leaving guild {}: {}", guild.id, e);
+        }
     }
 
-    fn reaction_add(&self, ctx: Context, add_reaction: Reaction) {
-        if let Err(e) = reaction_add(&ctx, &add_reaction) {
+    async fn reaction_add(&self, ctx: Context, add_reaction: Reaction) {
+        if let Err(e) = reaction_add(&ctx, &add_reaction).await {
             let author = {
-                match add_reaction.user(&ctx) {
+                match add_reaction.user(&ctx).await {
                     Ok(user) => user.name,
                     Err(_) => ":User Not Found:".to_owned(),
                 }
@@ -97,17 +223,14 @@ impl EventHandler for Handler {
                 ReactionError::ErrorFindingGuildMember(e) => {
                     warn!("Error finding {} in guild: {}", author, e);
                 }
-                ReactionError::ErrorAddingRole => {
-                    warn!("Error adding role to {}", author);
-                }
                 err => error!("{}", err),
             }
         }
     }
-    fn reaction_remove(&self, ctx: Context, removed_reaction: Reaction) {
-        if let Err(e) = reaction_remove(&ctx, &removed_reaction) {
+    async fn reaction_remove(&self, ctx: Context, removed_reaction: Reaction) {
+        if let Err(e) = reaction_remove(&ctx, &removed_reaction).await {
             let author = {
-                match removed_reaction.user(&ctx) {
+                match removed_reaction.user(&ctx).await {
                     Ok(user) => user.name,
                     Err(_) => ":User Not Found:".to_owned(),
                 }
@@ -117,9 +240,6 @@ impl EventHandler for Handler {
                 ReactionError::ErrorFindingGuildMember(e) => {
                     warn!("Error finding {} in guild: {}", author, e);
                 }
-                ReactionError::ErrorAddingRole => {
-                    warn!("Error removing role from {}", author);
-                }
                 err => error!("{}", err),
             }
         }
@@ -138,7 +258,7 @@ struct Info;
 
 #[group]
 #[owners_only]
-#[commands(quit, say, update)]
+#[commands(quit, say, update, prefix_stats, reload)]
 /// Commands that can only be ran by the owner of the bot
 struct Owners;
 
@@ -146,195 +266,263 @@ struct Owners;
 // This allows the bot owner to override certain permission checks
 // Intended to be a temp option, while the bot is in pre 1.0 development
 #[owner_privilege]
-#[commands(prefix, reaction_add)]
+#[commands(
+    prefix,
+    reaction_add,
+    reaction_list,
+    reaction_remove,
+    reaction_clear,
+    reaction_rules,
+    reaction_sync,
+    log_channel,
+    role_queue
+)]
 /// Commands to assist with adminstrating a server
 struct Admin;
 
+#[group]
+#[prefixes("panel")]
+#[owner_privilege]
+#[commands(create, edit, repost, list)]
+/// Commands to manage reaction role panels, messages the bot posts that list their reaction roles
+struct Panel;
+
+#[group]
+#[prefixes("config")]
+#[owner_privilege]
+#[commands(export, import)]
+/// Commands to move a server's setup to another server, or back it up
+struct Config;
+
 #[help]
 #[lacking_ownership = "hide"]
 #[lacking_role = "hide"]
 #[lacking_permissions = "strike"]
 #[wrong_channel = "Strike"]
-fn my_help(
-    context: &mut Context,
+async fn my_help(
+    context: &Context,
     msg: &Message,
     args: Args,
     help_options: &'static HelpOptions,
     groups: &[&'static CommandGroup],
     owners: HashSet<UserId>,
 ) -> CommandResult {
+    // Command examples leave the prefix off, so let people know what it is here
+    if args.is_empty() {
+        let prefixes = describe_prefixes(context, msg.guild_id).await?;
+        msg.channel_id.say(&context.http, prefixes).await?;
+    }
+    // The help is only handed back when it was sent, so nothing coming back means it failed
     help_commands::plain(context, msg, args, &help_options, groups, owners)
+        .await
+        .ok_or("Couldn't send the help message")?;
+
+    Ok(())
 }
 
-fn dynamic_prefix(ctx: &mut Context, msg: &Message) -> Option<String> {
-    // Make sure we can actually get the guild_id, if not there's
-    // no point to trying to find the prefix. Also means we can use
-    // unwrap for this later on, since we Guard check it's Some() here
-    msg.guild_id?;
-
-    // Try to keep the time we have the data lock as short as possible, since we intend to get the dynamic prefix quickly,
-    // most of our time will be spent aquiring the lock, so we just go ahead and grab out everything we might need at once.
-    let (hashmap_lock, fancy_db, runtime_lock) = {
-        let data = ctx.data.try_read()?;
-        let hashmap_lock = Arc::clone(data.get::<PrefixHashMapContainer>()?);
-        let fancy_db = Arc::clone(data.get::<PoolContainer>()?);
-        let runtime_lock = Arc::clone(data.get::<TokioContainer>()?);
-        (hashmap_lock, fancy_db, runtime_lock)
+#[hook]
+async fn prefix_or_default(ctx: &Context, msg: &Message) -> Option<String> {
+    let default = match default_prefix(ctx).await {
+        Ok(default) => default,
+        Err(e) => {
+            warn!("Failed to get the default prefix: {}", e);
+            return None;
+        }
     };
-
-    // We hold the lock for the rest of the function, as if the guild's prefix is not
-    // in the hashmap, after asking the db for the prefix, we'll want to insert that Into
-    // the hashmap regardless, so it makes little sense to hold the lock for a shorter period
-    // and aquire it again after the db responds.
-    let mut hashmap = hashmap_lock.lock().ok()?;
-
-    if let Some(prefix) = hashmap.get(msg.guild_id?.as_u64()) {
-        return Some(prefix.clone());
+    // Direct messages don't have a guild to look up prefixes for
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Some(default),
+    };
+    // If the prefixes can't be found, the guild might have its own, so answering to the default
+    // could run commands nobody meant to. Only mentioning the bot works until they can be found again.
+    let prefixes = match guild_prefixes(ctx, guild_id).await {
+        Ok(prefixes) => prefixes,
+        Err(e) => {
+            warn!("Failed to get the prefixes for {}: {}", guild_id, e);
+            return None;
+        }
+    };
+    if prefixes.is_empty() {
+        return Some(default);
     }
 
-    let database_prefix = {
-        let data = {
-            let mut runtime = runtime_lock.try_lock().ok()?;
-            runtime
-                .block_on(
-                    sqlx::query!(
-                        "SELECT prefix FROM guild WHERE id = $1",
-                        *msg.guild_id.unwrap().as_u64() as i64
-                    )
-                    .fetch_optional(fancy_db.pool()),
+    // The framework only takes one prefix back, so hand it whichever one the message was sent with.
+    // Messages that don't use any of them are left for the mention prefix.
+    Some(
+        matching_prefix(&prefixes, &msg.content)
+            .unwrap_or(&prefixes[0])
+            .clone(),
+    )
+}
+
+#[hook]
+async fn dispatch_error(context: &Context, message: &Message, error: DispatchError) {
+    match error {
+        DispatchError::Ratelimited(duration) => {
+            let _ = message
+                .channel_id
+                .say(
+                    &context.http,
+                    &format!("Try this again in {} seconds.", duration.as_secs()),
                 )
-                .ok()?
-        };
-        data?.prefix
-    };
+                .await;
+        }
+        DispatchError::OnlyForOwners => {}
+        DispatchError::IgnoredBot => {}
+        DispatchError::NotEnoughArguments { min, given } => {
+            let _ = message.channel_id.say(&context.http, format!("You did not provide enough arguments for this command, Minimum arguments are {}, you provided {}.", min, given)).await;
+        }
+        _ => warn!("Dispatch Error: {} failed: {:?}", message.content, error),
+    }
+}
 
-    hashmap.insert(*msg.guild_id?.as_u64(), database_prefix.clone());
-    Some(database_prefix)
+// TODO: Better error handling
+#[hook]
+async fn after(context: &Context, message: &Message, command_name: &str, error: CommandResult) {
+    if let Err(why) = error {
+        let why = redact(&why.to_string());
+        let _ = message
+            .channel_id
+            .say(
+                &context.http,
+                format!(
+                    "The command {} has errored: ``{}``\nPlease try again later",
+                    command_name, why
+                ),
+            )
+            .await;
+        warn!(
+            "Command `{}` triggered by `{}` has errored: \n{}",
+            command_name,
+            message.author.tag(),
+            why
+        );
+        report_error(ErrorRecord::from_command(message, command_name, why));
+    }
 }
 
-fn main() {
+#[tokio::main]
+async fn main() {
     dotenv::dotenv().ok();
     sentry::integrations::env_logger::init(None, Default::default());
 
-    let config = Arc::new(Mutex::new(config::Config::default()));
-
-    let (token, enviroment) = {
-        let mut settings = config.lock().unwrap_or_else(|err| {
-            error!("Unable to get config lock, bailing...");
-            panic!("{}", err);
-        });
-
-        settings
-            .set_default("debug", false)
-            .expect("Unable to set a default value for debug");
-        settings
-            .merge(config::File::with_name("settings"))
-            .expect("No file called Settings.toml in same folder as bot");
-
-        let token = {
-            match std::env::var("DISCORD_TOKEN") {
-                Ok(token) => token,
-                Err(_) => settings
-                    .get_str("token")
-                    .expect("No token/token value set in Settings file"),
-            }
-        };
-        let enviroment = {
-            if !settings.get_bool("debug").unwrap_or(false) {
-                "Production"
-            } else {
-                "Staging"
-            }
-        };
-
-        (token, enviroment)
+    let settings_file = SettingsFile::from_args().unwrap_or_else(|e| panic!("{}", e));
+    let settings = Settings::load(&settings_file).unwrap_or_else(|e| {
+        panic!(
+            "Couldn't start with the settings from {}: {}",
+            settings_file.path.display(),
+            e
+        )
+    });
+    // The token is masked when settings are printed, this is the only place it's taken out
+    let token = settings.token.expose().to_owned();
+    hide_secret(&token);
+    debug!("Loaded settings: {:?}", settings);
+
+    let _guard = match &settings.sentry_dsn {
+        Some(dsn) => {
+            let guard = sentry::init((
+                dsn.as_str(),
+                sentry::ClientOptions {
+                    max_breadcrumbs: 50,
+                    sample_rate: settings.sentry_sample_rate,
+                    environment: Some(settings.environment().into()),
+                    release: sentry::release_name!(),
+                    before_send: Some(Arc::new(|mut event| {
+                        event.message = event.message.as_deref().map(redact);
+                        for exception in event.exception.values.iter_mut() {
+                            exception.value = exception.value.as_deref().map(redact);
+                        }
+                        for breadcrumb in event.breadcrumbs.values.iter_mut() {
+                            breadcrumb.message = breadcrumb.message.as_deref().map(redact);
+                        }
+                        Some(event)
+                    })),
+                    ..Default::default()
+                },
+            ));
+            sentry::integrations::panic::register_panic_handler();
+            Some(guard)
+        }
+        None => {
+            info!("No sentry_dsn is set, so errors won't be sent to Sentry");
+            None
+        }
     };
+    if let Some(error_file) = &settings.error_file {
+        start_error_file(error_file.clone());
+    }
 
-    let tokio_runtime = Arc::new(Mutex::new(
-        tokio::runtime::Runtime::new().expect("Couldn't start tokio runtime"),
-    ));
-
-    let pool = Arc::new(FancyPool::new(Arc::clone(&tokio_runtime)));
-
-    let _guard = sentry::init((
-        "https://c667c4bf6a704b0f802fa075c98f8c03@sentry.io/1340627",
-        sentry::ClientOptions {
-            max_breadcrumbs: 50,
-            environment: Some(enviroment.into()),
-            release: sentry::release_name!(),
-            ..Default::default()
+    let pool = Arc::new(FancyPool::new().await);
+
+    let http = Http::new_with_token(&token);
+    let mut owners: HashSet<UserId> = settings.owners.iter().map(|id| UserId(*id)).collect();
+    match http.get_current_application_info().await {
+        // The owner of a team's application is a placeholder user, so the team's members are the owners,
+        // apart from anyone who hasn't accepted their invite yet
+        Ok(info) => match info.team {
+            Some(team) => owners.extend(
+                team.members
+                    .iter()
+                    .filter(|member| member.membership_state == MembershipState::Accepted)
+                    .map(|member| member.user.id),
+            ),
+            None => {
+                owners.insert(info.owner.id);
+            }
         },
-    ));
-
-    sentry::integrations::panic::register_panic_handler();
-
-    let mut client = Client::new(&token, Handler).expect("Err creating client");
+        Err(why) => panic!("Couldn't get application info: {:?}", why),
+    };
+    let bot_id = match http.get_current_user().await {
+        Ok(user) => user.id,
+        Err(why) => panic!("Couldn't get the bot's user: {:?}", why),
+    };
 
-    let prefix_hash_arc: Arc<Mutex<HashMap<u64, String>>> = Arc::new(Mutex::new(HashMap::new()));
+    let framework = StandardFramework::new()
+        .configure(|c| {
+            c.owners(owners)
+                .dynamic_prefix(prefix_or_default)
+                // Mentioning the bot always works, for when nobody remembers the prefix
+                .on_mention(Some(bot_id))
+                .ignore_webhooks(false)
+                .case_insensitivity(true)
+        })
+        .on_dispatch_error(dispatch_error)
+        .after(after)
+        .help(&MY_HELP)
+        .group(&GENERAL_GROUP)
+        .group(&OWNERS_GROUP)
+        .group(&INFO_GROUP)
+        .group(&ADMIN_GROUP)
+        .group(&PANEL_GROUP)
+        .group(&CONFIG_GROUP);
+
+    let mut client = Client::builder(&token)
+        .event_handler(Handler)
+        .framework(framework)
+        .await
+        .expect("Err creating client");
+
+    let prefix_hash_arc = Arc::new(SernMutex::new(LruCache::new(settings.prefix_cache_size)));
+
+    let reaction_messages = load_reaction_messages(&pool)
+        .await
+        .expect("Unable to load reaction roles");
+    let reaction_message_arc = Arc::new(RwLock::new(reaction_messages));
 
     {
-        let mut data = client.data.write();
+        let mut data = client.data.write().await;
         data.insert::<ShardManagerContainer>(Arc::clone(&client.shard_manager));
-        data.insert::<SettingsContainer>(Arc::clone(&config));
-        data.insert::<TokioContainer>(Arc::clone(&tokio_runtime));
+        data.insert::<SettingsContainer>(Arc::new(RwLock::new(settings)));
+        data.insert::<SettingsFileContainer>(settings_file);
         data.insert::<PoolContainer>(Arc::clone(&pool));
         data.insert::<PrefixHashMapContainer>(Arc::clone(&prefix_hash_arc));
+        data.insert::<ReactionMessageCacheContainer>(Arc::clone(&reaction_message_arc));
+        data.insert::<RoleQueueContainer>(Arc::new(Notify::new()));
     }
 
-    let owners = match client.cache_and_http.http.get_current_application_info() {
-        Ok(info) => {
-            let mut set = HashSet::new();
-            set.insert(info.owner.id);
-
-            set
-        }
-        Err(why) => panic!("Couldn't get application info: {:?}", why),
-    };
-
-    client.with_framework(
-        StandardFramework::new()
-            .configure(|c| {
-                c.owners(owners)
-                    .dynamic_prefix(|ctx: &mut Context, msg: &Message| {
-                        // Seperate function so dynamic prefix can look cleaner
-                        // (this allows for us to use return None, when dynamic_prefix
-                        // has no results, Allowing us here, to use a "default" prefix
-                        // in the case that it is None for any reason)
-                        if let Some(prefix) = dynamic_prefix(ctx, msg) {
-                            return Some(prefix);
-                        }
-                        Some("a.".to_string())
-                    })
-                    .ignore_webhooks(false)
-                    .case_insensitivity(true)
-            })
-            .on_dispatch_error(|context, message, error| match error {
-                DispatchError::Ratelimited(seconds) => {
-                    let _ = message.channel_id.say(
-                        &context.http,
-                        &format!("Try this again in {} seconds.", seconds),
-                    );
-                },
-                DispatchError::OnlyForOwners => {},
-                DispatchError::IgnoredBot => {},
-                DispatchError::NotEnoughArguments{ min, given} => {
-                    let _ = message.channel_id.say(&context.http, format!("You did not provide enough arguments for this command, Minimum arguments are {}, you provided {}.", min, given));
-                }
-                _ => warn!("Dispatch Error: {} failed: {:?}", message.content, error),
-            })
-            // TODO: Better error handling
-            .after(|context, message, command_name, error| if let Err(why) = error {
-                    let _ = message.channel_id.say(&context.http, format!("The command {} has errored: ``{}``\nPlease try again later", command_name, why.0));
-                    warn!("Command `{}` triggered by `{}` has errored: \n{}", command_name, message.author.tag(), why.0);
-            })
-            .help(&MY_HELP)
-            .group(&GENERAL_GROUP)
-            .group(&OWNERS_GROUP)
-            .group(&INFO_GROUP)
-            .group(&ADMIN_GROUP)
-    );
-
-    if let Err(why) = client.start_autosharded() {
+    if let Err(why) = client.start_autosharded().await {
         error!("Client error: {:?}", why);
     }
 }